@@ -0,0 +1,21 @@
+#version 430 core
+
+in vec3 position;
+in layout(location=1) vec4 inColors; //Get color matrix as input
+out layout(location=1) vec4 outColors; //Output color matrix to fragment shader
+
+in layout(location=2) vec3 inNormals; //Get normals as input
+out layout(location=2) vec3 outNormals; //Output normals to the fragment shader
+out layout(location=3) vec3 outPosition; //Output the world space position to the fragment shader, for lights that have a position
+
+in layout(location=6) mat4 instanceModelMatrix; //Per-instance model matrix, takes up locations 6 to 9. Set up in instancing.rs
+uniform layout(location=5) mat4 viewProjectionMatrix; //This is the view projection matrix passed from the render loop in main.rs
+
+
+void main()
+{
+    gl_Position = vec4(position, 1.0f) * instanceModelMatrix * viewProjectionMatrix; //Transform, same as simple.vert but with the model matrix of this instance
+    outColors = inColors; //Assign out colors the value of the in colors to be passed to the fragment shader
+    outNormals = normalize(inNormals * transpose(inverse(mat3(instanceModelMatrix)))); //Same normal matrix as simple.vert gets from draw_scene, but worked out here since it is different for every instance
+    outPosition = (vec4(position, 1.0f) * instanceModelMatrix).xyz;
+}
@@ -0,0 +1,6 @@
+#version 430 core
+
+//The shadow map only needs depth, which is written without doing anything here
+void main()
+{
+}
@@ -0,0 +1,12 @@
+#version 430 core
+
+in vec3 position;
+
+in layout(location=6) mat4 instanceModelMatrix; //Per-instance model matrix, like in instanced.vert
+uniform layout(location=5) mat4 viewProjectionMatrix; //The light's view projection matrix, from shadow.rs
+
+
+void main()
+{
+    gl_Position = vec4(position, 1.0f) * instanceModelMatrix * viewProjectionMatrix; //Only the depth is kept
+}
@@ -2,13 +2,94 @@
 
 layout(location=1) in vec4 inColors; //Get the color from location 1 (output from vertex shader)
 layout(location=2) in vec3 inNormals; //Get the normals from location 2 (output from vertex shader)
+layout(location=3) in vec3 inPosition; //Get the world space position from location 3 (output from vertex shader)
 out vec4 color;
 
-vec3 lightDirection =  normalize(vec3(0.8, -0.5, 0.6));
+//Set from lighting.rs when the shader is compiled
+#ifndef MAX_LIGHTS
+#define MAX_LIGHTS 8
+#endif
+
+//Kinds of lights, must match LightKind::id in lighting.rs
+const int DIRECTIONAL = 0;
+const int POINT = 1;
+const int SPOT = 2;
+
+struct Light {
+    int kind;
+    vec3 color; //Already multiplied by the intensity
+    vec3 position;
+    vec3 direction; //Which way the light shines
+    vec3 attenuation; //Constant, linear and quadratic falloff with distance
+    float innerCutoff; //Cosine of the angle where a spot light starts to fade out
+    float outerCutoff; //Cosine of the angle where it has faded out
+};
+
+uniform Light lights[MAX_LIGHTS];
+uniform int lightCount;
+uniform vec3 ambientLight;
+uniform float specularStrength;
+uniform float shininess;
+uniform vec3 cameraPosition;
+
+//Shadows, see shadow.rs
+uniform sampler2DShadow shadowMap;
+uniform mat4 lightSpaceMatrix; //Light's view projection matrix the shadow map was drawn with
+uniform int shadowLight; //Index of the light that casts shadows, -1 for none
+uniform float shadowBias; //In shadow map depth
+uniform float shadowSlopeBias;
+uniform int shadowFilterRadius;
+
+//How much of the fragment the shadow light reaches, from 0 in shadow to 1 fully lit
+float shadowFactor(float lambert)
+{
+    vec4 lightSpace = vec4(inPosition, 1.0) * lightSpaceMatrix;
+    vec3 coords = lightSpace.xyz / lightSpace.w * 0.5 + 0.5;
+    if (coords.z > 1.0) {
+        return 1.0; //Further from the light than the shadow map reaches
+    }
+    float depth = coords.z - shadowBias - shadowSlopeBias * (1.0 - lambert);
+    vec2 texel = 1.0 / vec2(textureSize(shadowMap, 0));
+    float lit = 0.0;
+    for (int x = -shadowFilterRadius; x <= shadowFilterRadius; x++) {
+        for (int y = -shadowFilterRadius; y <= shadowFilterRadius; y++) {
+            lit += texture(shadowMap, vec3(coords.xy + vec2(x, y) * texel, depth));
+        }
+    }
+    float width = float(2 * shadowFilterRadius + 1);
+    return lit / (width * width);
+}
 
 void main()
 {
-    float maxi = max(0, dot(inNormals , (-lightDirection)));
-    color = vec4(inColors[0] * maxi, inColors[1] * maxi, inColors[2] * maxi, inColors[3]);//Color the geometry
-    //color = inColors * vec4(max(vec3(0),inNormals * (-lightDirection)), 1.0);//Use color matrix to color the geometry
-}
\ No newline at end of file
+    vec3 normal = normalize(inNormals);
+    vec3 toCamera = normalize(cameraPosition - inPosition);
+
+    //Blinn-Phong, the diffuse light is tinted by the vertex color and the specular highlights are not
+    vec3 diffuse = ambientLight;
+    vec3 specular = vec3(0.0);
+    for (int i = 0; i < min(lightCount, MAX_LIGHTS); i++) {
+        vec3 toLight = -lights[i].direction;
+        float strength = 1.0;
+        if (lights[i].kind != DIRECTIONAL) {
+            vec3 offset = lights[i].position - inPosition;
+            float distance = length(offset);
+            toLight = offset / distance;
+            vec3 attenuation = lights[i].attenuation;
+            strength = 1.0 / max(attenuation.x + attenuation.y * distance + attenuation.z * distance * distance, 0.0001);
+            if (lights[i].kind == SPOT) {
+                strength *= smoothstep(lights[i].outerCutoff, lights[i].innerCutoff, dot(-toLight, lights[i].direction));
+            }
+        }
+
+        float lambert = max(dot(normal, toLight), 0.0);
+        if (i == shadowLight && lambert > 0.0) {
+            strength *= shadowFactor(lambert);
+        }
+        vec3 halfway = normalize(toLight + toCamera);
+        float highlight = lambert > 0.0 ? pow(max(dot(normal, halfway), 0.0), shininess) : 0.0; //No highlights on the dark side
+        diffuse += lights[i].color * lambert * strength;
+        specular += lights[i].color * highlight * strength;
+    }
+    color = vec4(inColors.rgb * diffuse + specular * specularStrength, inColors.a); //Color the geometry
+}
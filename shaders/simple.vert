@@ -6,15 +6,18 @@ out layout(location=1) vec4 outColors; //Output color matrix to fragment shader
 
 in layout(location=2) vec3 inNormals; //Get normals as input
 out layout(location=2) vec3 outNormals; //Output normals to the fragment shader
+out layout(location=3) vec3 outPosition; //Output the world space position to the fragment shader, for lights that have a position
 
 uniform layout(location=3) mat4 matrix; //uniform matrix passed in from our main loop at location 3. Contains the transformation matrix.
 uniform layout(location=4) mat4 modelMatrix; //This is the model matrix passed from draw_scene fucntion in main.rs
 uniform layout(location=5) mat4 viewProjectionMatrix; //This is the view projection matrix passed from draw_scene fucntion in main.rs
+uniform layout(location=6) mat3 normalMatrix; //Inverse transpose of the model matrix, so normals stay at right angles to surfaces that are scaled unevenly. From draw_scene in main.rs
 
 
 void main()
 {
     gl_Position = vec4(position, 1.0f) * matrix; //Transform 
     outColors = inColors; //Assign out colors the value of the in colors to be passed to the fragment shader
-    outNormals = normalize(mat3(modelMatrix) * inNormals);; //Assign out normals the value of the in normals to be passed to the fragment shader
+    outNormals = normalize(inNormals * normalMatrix); //Assign out normals the value of the in normals to be passed to the fragment shader. Row vector, like the position
+    outPosition = (vec4(position, 1.0f) * modelMatrix).xyz;
 }
\ No newline at end of file
@@ -0,0 +1,378 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+
+use crate::behaviour::{Behaviour, BehaviourDescription, FrameContext};
+use crate::scene_file::Rotation;
+use crate::scene_graph::SceneNode;
+
+// Keyframed animation of node positions, rotations and scales. Clips are played by an AnimationPlayer, which is a
+// behaviour so it can be attached to a node and described in scene files like any other. Keyframe values are
+// written the same way as node properties in scene files, e.g. rotations can be given as Euler angles.
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Interpolation {
+    Step, // Hold each keyframe's value until the next one
+    Linear,
+    Cubic, // Catmull-Rom for vectors, squad for rotations
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LoopMode {
+    Once, // Stay on the last frame when done
+    Loop,
+    PingPong, // Play forwards, then backwards, and so on
+}
+
+impl LoopMode {
+    // Where on a timeline of the given duration we are, time seconds after starting to play it
+    pub fn wrap(&self, time: f32, duration: f32) -> f32 {
+        if duration <= 0.0 {
+            return 0.0;
+        }
+        match self {
+            LoopMode::Once => time.max(0.0).min(duration),
+            LoopMode::Loop => time.rem_euclid(duration),
+            LoopMode::PingPong => {
+                let t = time.rem_euclid(2.0 * duration);
+                if t > duration { 2.0 * duration - t } else { t }
+            },
+        }
+    }
+}
+
+pub trait Interpolate: Copy {
+    fn lerp(a: &Self, b: &Self, t: f32) -> Self;
+    // Interpolate between b and c, with a and d being the keyframes before and after them
+    fn cubic(a: &Self, b: &Self, c: &Self, d: &Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn lerp(a: &f32, b: &f32, t: f32) -> f32 {
+        a + (b - a) * t
+    }
+
+    fn cubic(a: &f32, b: &f32, c: &f32, d: &f32, t: f32) -> f32 {
+        let (t2, t3) = (t * t, t * t * t);
+        (b * 2.0 + (c - a) * t + (a * 2.0 - b * 5.0 + c * 4.0 - d) * t2 + (b * 3.0 - a - c * 3.0 + d) * t3) * 0.5
+    }
+}
+
+impl Interpolate for glm::Vec3 {
+    fn lerp(a: &glm::Vec3, b: &glm::Vec3, t: f32) -> glm::Vec3 {
+        glm::lerp(a, b, t)
+    }
+
+    fn cubic(a: &glm::Vec3, b: &glm::Vec3, c: &glm::Vec3, d: &glm::Vec3, t: f32) -> glm::Vec3 {
+        let (t2, t3) = (t * t, t * t * t);
+        (b * 2.0 + (c - a) * t + (a * 2.0 - b * 5.0 + c * 4.0 - d) * t2 + (b * 3.0 - a - c * 3.0 + d) * t3) * 0.5
+    }
+}
+
+// Logarithm of a unit quaternion, as the vector part of a pure quaternion. glm::quat_log is NaN for the identity
+fn quat_log_unit(q: &glm::Quat) -> glm::Vec3 {
+    let v = q.coords.xyz();
+    let sin_angle = glm::length(&v);
+    if sin_angle < 1e-6 {
+        return v;
+    }
+    v / sin_angle * sin_angle.atan2(q.coords.w)
+}
+
+// Inverse of quat_log_unit
+fn quat_exp_pure(v: &glm::Vec3) -> glm::Quat {
+    let angle = glm::length(v);
+    if angle < 1e-6 {
+        return glm::quat_normalize(&glm::quat(v.x, v.y, v.z, 1.0));
+    }
+    let axis = v / angle * angle.sin();
+    glm::quat(axis.x, axis.y, axis.z, angle.cos())
+}
+
+// The inner control point squad uses at q, given its neighbours
+fn squad_control_point(previous: &glm::Quat, q: &glm::Quat, next: &glm::Quat) -> glm::Quat {
+    let inverse = glm::quat_inverse(q);
+    let sum = quat_log_unit(&(inverse * next)) + quat_log_unit(&(inverse * previous));
+    glm::quat_normalize(&(q * quat_exp_pure(&(sum * -0.25))))
+}
+
+// The same rotation as q, flipped if needed to be on the same side of the 4D sphere as reference
+fn same_hemisphere(reference: &glm::Quat, q: &glm::Quat) -> glm::Quat {
+    if glm::quat_dot(reference, q) < 0.0 { -q } else { *q }
+}
+
+impl Interpolate for [f32; 3] {
+    fn lerp(a: &[f32; 3], b: &[f32; 3], t: f32) -> [f32; 3] {
+        <glm::Vec3 as Interpolate>::lerp(&glm::make_vec3(a), &glm::make_vec3(b), t).into()
+    }
+
+    fn cubic(a: &[f32; 3], b: &[f32; 3], c: &[f32; 3], d: &[f32; 3], t: f32) -> [f32; 3] {
+        <glm::Vec3 as Interpolate>::cubic(&glm::make_vec3(a), &glm::make_vec3(b), &glm::make_vec3(c), &glm::make_vec3(d), t).into()
+    }
+}
+
+impl Interpolate for glm::Quat {
+    fn lerp(a: &glm::Quat, b: &glm::Quat, t: f32) -> glm::Quat {
+        glm::quat_slerp(a, b, t)
+    }
+
+    fn cubic(a: &glm::Quat, b: &glm::Quat, c: &glm::Quat, d: &glm::Quat, t: f32) -> glm::Quat {
+        let a = same_hemisphere(b, a);
+        let c = same_hemisphere(b, c);
+        let d = same_hemisphere(&c, d);
+        let s1 = squad_control_point(&a, b, &c);
+        let s2 = squad_control_point(b, &c, &d);
+        glm::quat_slerp(&glm::quat_slerp(b, &c, t), &glm::quat_slerp(&s1, &s2, t), 2.0 * t * (1.0 - t))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Keyframe<T> {
+    pub time: f32,
+    pub value: T,
+}
+
+// Keyframes have to be sorted by time. Tracks loaded from files are sorted when they are loaded, keyframes at the
+// same time staying in the order they were written in
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(bound(deserialize = "T: Deserialize<'de>"))]
+pub struct Track<T> {
+    pub interpolation: Interpolation,
+    #[serde(deserialize_with = "sorted_keyframes")]
+    pub keyframes: Vec<Keyframe<T>>,
+}
+
+fn sorted_keyframes<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Vec<Keyframe<T>>, D::Error> {
+    let mut keyframes = Vec::<Keyframe<T>>::deserialize(deserializer)?;
+    if let Some(keyframe) = keyframes.iter().find(|k| !k.time.is_finite()) {
+        return Err(D::Error::custom(format!("keyframe time {} is not a number of seconds", keyframe.time)));
+    }
+    keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
+    Ok(keyframes)
+}
+
+impl<T: Interpolate> Track<T> {
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |k| k.time)
+    }
+
+    // The value at the given time, holding the first and last values before and after the keyframes
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let keys = &self.keyframes;
+        let first = keys.first()?;
+        let last = keys.last()?;
+        if time <= first.time {
+            return Some(first.value);
+        }
+        if time >= last.time {
+            return Some(last.value);
+        }
+
+        let i = keys.partition_point(|k| k.time <= time) - 1; //keys[i] is the last keyframe at or before time
+        let (from, to) = (&keys[i], &keys[i + 1]);
+        let t = (time - from.time) / (to.time - from.time);
+        Some(match self.interpolation {
+            Interpolation::Step => from.value,
+            Interpolation::Linear => T::lerp(&from.value, &to.value, t),
+            Interpolation::Cubic => {
+                let before = &keys[i.saturating_sub(1)];
+                let after = &keys[(i + 2).min(keys.len() - 1)];
+                T::cubic(&before.value, &from.value, &to.value, &after.value, t)
+            },
+        })
+    }
+}
+
+impl Interpolate for Rotation {
+    fn lerp(a: &Rotation, b: &Rotation, t: f32) -> Rotation {
+        Rotation::from_quat(&<glm::Quat as Interpolate>::lerp(&a.to_quat(), &b.to_quat(), t))
+    }
+
+    fn cubic(a: &Rotation, b: &Rotation, c: &Rotation, d: &Rotation, t: f32) -> Rotation {
+        Rotation::from_quat(&<glm::Quat as Interpolate>::cubic(&a.to_quat(), &b.to_quat(), &c.to_quat(), &d.to_quat(), t))
+    }
+}
+
+// The tracks animating one node, which is found by its path relative to the node the player is attached to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeTracks {
+    #[serde(default)]
+    pub node: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Track<[f32; 3]>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Track<Rotation>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<Track<[f32; 3]>>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AnimationClip {
+    pub name: String,
+    pub loop_mode: LoopMode,
+    pub tracks: Vec<NodeTracks>,
+}
+
+impl AnimationClip {
+    pub fn duration(&self) -> f32 {
+        self.tracks.iter().fold(0.0, |duration, t| {
+            let durations = [t.position.as_ref().map(Track::duration), t.rotation.as_ref().map(Track::duration), t.scale.as_ref().map(Track::duration)];
+            durations.iter().flatten().fold(duration, |a, &b| f32::max(a, b))
+        })
+    }
+
+    // Set the animated properties of the nodes below root to how they are time seconds into the clip
+    pub fn apply(&self, root: &mut SceneNode, time: f32) {
+        let time = self.loop_mode.wrap(time, self.duration());
+        for tracks in &self.tracks {
+            if let Some(node) = root.find(&tracks.node) {
+                if let Some(position) = tracks.position.as_ref().and_then(|track| track.sample(time)) {
+                    node.position = glm::make_vec3(&position);
+                }
+                if let Some(rotation) = tracks.rotation.as_ref().and_then(|track| track.sample(time)) {
+                    node.orientation = rotation.to_quat();
+                }
+                if let Some(scale) = tracks.scale.as_ref().and_then(|track| track.sample(time)) {
+                    node.scale = glm::make_vec3(&scale);
+                }
+            }
+        }
+    }
+}
+
+// Plays one of its clips on the node it is attached to and the nodes below it. How far into the clip it is comes
+// from the simulation time, so seeking moves the animation along with everything else
+pub struct AnimationPlayer {
+    pub clips: Vec<AnimationClip>,
+    pub speed: f32,
+    playing: Option<usize>,
+    start_time: Option<f32>, // Simulation time the clip started playing at, the time of the first update after play
+}
+
+impl AnimationPlayer {
+    pub fn new(clips: Vec<AnimationClip>, speed: f32) -> AnimationPlayer {
+        AnimationPlayer { clips, speed, playing: None, start_time: None }
+    }
+
+    // Start the clip with the given name from the beginning. Returns false if there is no such clip
+    pub fn play(&mut self, name: &str) -> bool {
+        self.playing = self.clips.iter().position(|clip| clip.name == name);
+        self.start_time = None;
+        self.playing.is_some()
+    }
+}
+
+impl Behaviour for AnimationPlayer {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        if let Some(i) = self.playing {
+            let start_time = *self.start_time.get_or_insert(ctx.time);
+            self.clips[i].apply(node, (ctx.time - start_time).max(0.0) * self.speed); //Seeking to before the start shows the first frame
+        }
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::Animation {
+            clips: self.clips.clone(),
+            playing: self.playing.map(|i| self.clips[i].name.clone()),
+            speed: self.speed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(interpolation: Interpolation, keyframes: &[(f32, f32)]) -> Track<f32> {
+        Track { interpolation, keyframes: keyframes.iter().map(|&(time, value)| Keyframe { time, value }).collect() }
+    }
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn sampling_a_linear_track() {
+        let track = track(Interpolation::Linear, &[(1.0, 10.0), (2.0, 20.0), (4.0, 0.0)]);
+        assert_eq!(track.duration(), 4.0);
+        assert_eq!(track.sample(0.0), Some(10.0)); //Before the first key
+        assert_eq!(track.sample(1.0), Some(10.0)); //On keys
+        assert_eq!(track.sample(2.0), Some(20.0));
+        assert_eq!(track.sample(4.0), Some(0.0));
+        assert!(approx(track.sample(1.5).unwrap(), 15.0)); //Between keys
+        assert!(approx(track.sample(3.5).unwrap(), 5.0));
+        assert_eq!(track.sample(9.0), Some(0.0)); //After the end
+        assert_eq!(Track::<f32> { interpolation: Interpolation::Linear, keyframes: vec![] }.sample(1.0), None);
+    }
+
+    #[test]
+    fn sampling_step_and_cubic_tracks() {
+        let keys = [(0.0, 0.0), (1.0, 1.0), (2.0, 4.0), (3.0, 9.0)];
+        let step = track(Interpolation::Step, &keys);
+        assert_eq!(step.sample(1.0), Some(1.0));
+        assert_eq!(step.sample(1.99), Some(1.0));
+        assert_eq!(step.sample(2.0), Some(4.0));
+
+        //Catmull-Rom passes through every key
+        let cubic = track(Interpolation::Cubic, &keys);
+        for &(time, value) in &keys {
+            assert!(approx(cubic.sample(time).unwrap(), value), "{}", time);
+        }
+        let between = cubic.sample(1.5).unwrap();
+        assert!(between > 1.0 && between < 4.0);
+    }
+
+    #[test]
+    fn keyframes_are_sorted_when_loaded() {
+        let track: Track<f32> = ron::de::from_str("(interpolation: Linear, keyframes: [(time: 2.0, value: 20.0), (time: 0.0, value: 0.0), (time: 1.0, value: 10.0)])").unwrap();
+        assert_eq!(track.keyframes.iter().map(|k| k.time).collect::<Vec<_>>(), vec![0.0, 1.0, 2.0]);
+        assert!(approx(track.sample(0.5).unwrap(), 5.0));
+        assert!(ron::de::from_str::<Track<f32>>("(interpolation: Linear, keyframes: [(time: NaN, value: 0.0)])").is_err());
+    }
+
+    #[test]
+    fn looping_clips() {
+        let tracks = NodeTracks { node: String::new(), position: None, rotation: None, scale: None };
+        let mut clip = AnimationClip { name: "bob".to_string(), loop_mode: LoopMode::Loop, tracks: vec![tracks] };
+        clip.tracks[0].position = Some(Track {
+            interpolation: Interpolation::Linear,
+            keyframes: vec![Keyframe { time: 0.0, value: [0.0, 0.0, 0.0] }, Keyframe { time: 2.0, value: [0.0, 4.0, 0.0] }],
+        });
+        assert_eq!(clip.duration(), 2.0);
+
+        let mut node = SceneNode::new();
+        clip.apply(&mut node, 5.0); //Half way through the third time
+        assert!(approx(node.position.y, 2.0));
+
+        clip.loop_mode = LoopMode::Once;
+        clip.apply(&mut node, 5.0);
+        assert!(approx(node.position.y, 4.0));
+
+        clip.loop_mode = LoopMode::PingPong;
+        clip.apply(&mut node, 3.5); //On the way back
+        assert!(approx(node.position.y, 1.0));
+    }
+
+    #[test]
+    fn player_steps_through_the_clip() {
+        let clip: AnimationClip = ron::de::from_str("(name: \"grow\", loop_mode: Loop, tracks: [(scale: Some((interpolation: Linear, keyframes: [(time: 0.0, value: (1.0, 1.0, 1.0)), (time: 1.0, value: (3.0, 3.0, 3.0))])))])").unwrap();
+        let mut player = AnimationPlayer::new(vec![clip], 2.0);
+        assert!(!player.play("shrink"));
+        assert!(player.play("grow"));
+
+        //Starts at the time of the first update
+        let mut node = SceneNode::new();
+        player.update(&mut node, &FrameContext { time: 1.0, ..Default::default() });
+        assert!(approx(node.scale.x, 1.0));
+        player.update(&mut node, &FrameContext { time: 1.25, delta_time: 0.25, seeked: false });
+        assert!(approx(node.scale.x, 2.0));
+        player.update(&mut node, &FrameContext { time: 1.5, delta_time: 0.25, seeked: false });
+        assert!(approx(node.scale.x, 1.0)); //Twice as fast, so one whole loop already
+
+        //Seeking moves the clip with the simulation time
+        player.update(&mut node, &FrameContext { time: 1.125, delta_time: 0.0, seeked: true });
+        assert!(approx(node.scale.x, 1.5));
+        player.update(&mut node, &FrameContext { time: 0.0, delta_time: 0.0, seeked: true });
+        assert!(approx(node.scale.x, 1.0));
+    }
+}
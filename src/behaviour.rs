@@ -0,0 +1,318 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{Deserialize, Serialize};
+
+use crate::animation::{AnimationClip, AnimationPlayer};
+use crate::door::DoorBehaviour;
+use crate::flight::FlightInput;
+use crate::mission::{check_waypoints, Waypoint, WaypointBehaviour, WaypointFlight};
+use crate::rotor::{Rotor, RotorBehaviour, RotorState};
+use crate::scene_graph::{EulerOrder, SceneNode};
+use crate::steering::{FlockBehaviour, FlockParameters, FormationBehaviour, FormationParameters, FormationSlot};
+use crate::toolbox::{AltitudeProfile, FigureEight, FlightPath, FlightSequence, Heading, SplineKind};
+use crate::tween::{Easing, TweenProperty};
+
+// What behaviours get to know about the current frame. Built by the render loop, or by hand to step behaviours
+// through made up time
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameContext {
+    pub time: f32, // Seconds since the simulation started
+    pub delta_time: f32, // Seconds since the previous update
+    pub seeked: bool, // The time jumped here instead of moving forward, see Clock::seek. delta_time is 0 then
+}
+
+// Things that can be asked of the behaviours in a subtree, see SceneNode::send_command
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    StartRotors,
+    StopRotors,
+    ToggleRotors,
+    OpenDoor,
+    CloseDoor,
+    ToggleDoor,
+    Pilot(FlightInput), // The controls of a helicopter flown by hand
+}
+
+// Logic attached to a scene node, run every frame before the transformations are updated.
+// See SceneNode::update_behaviours
+pub trait Behaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext);
+
+    // Called for commands sent to the node or one of its ancestors. Behaviours ignore the ones they do not understand
+    fn command(&mut self, _command: Command) {}
+
+    // How to write this behaviour to a scene file, if it can be
+    fn describe(&self) -> Option<BehaviourDescription> {
+        None
+    }
+
+    // What to call the behaviour in messages, the name of its type without the module path or type parameters
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.split('<').next().unwrap().rsplit("::").next().unwrap()
+    }
+}
+
+// Behaviours as they are written in scene files
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BehaviourDescription {
+    PathFollow {
+        time_offset: f32,
+        #[serde(default = "default_path_size")]
+        size: f32,
+        #[serde(default = "default_circuit_speed")]
+        speed: f32,
+        #[serde(default)]
+        altitude: AltitudeProfile,
+    },
+    FlightPath {
+        points: Vec<[f32; 3]>,
+        spline: SplineKind,
+        #[serde(default)]
+        closed: bool,
+        speed: f32, // Units per second
+        #[serde(default)]
+        distance_offset: f32, // How far along the path to start
+    },
+    FlightSequence {
+        sequence: FlightSequence,
+        #[serde(default)]
+        start_time: f32, // Seconds to wait on the ground before taking off
+    },
+    Waypoints {
+        waypoints: Vec<Waypoint>,
+        #[serde(default)]
+        looping: bool,
+        #[serde(default)]
+        start_time: f32,
+    },
+    Spin { axis: [f32; 3], rpm: f32 },
+    Rotors {
+        main_rotor: String, // Path to the main rotor node
+        main_axis: [f32; 3],
+        tail_rotor: String,
+        tail_axis: [f32; 3],
+        target_rpm: f32,
+        spin_up: f32,
+        spin_down: f32,
+        gear_ratio: f32,
+        #[serde(default)]
+        state: RotorState,
+    },
+    Door {
+        door: String, // Path to the door node
+        hinge: [f32; 3],
+        axis: [f32; 3],
+        open_angle: f32,
+        open_time: f32,
+        #[serde(default = "door_easing")]
+        easing: Easing,
+        #[serde(default)]
+        open: bool,
+    },
+    Formation {
+        leader: String, // Path to the leader
+        slots: Vec<FormationSlot>,
+        #[serde(default)]
+        parameters: FormationParameters,
+    },
+    Flock {
+        tag: String, // Every node below with this tag is part of the flock
+        #[serde(default)]
+        parameters: FlockParameters,
+    },
+    Tween {
+        #[serde(default)]
+        node: String, // Path to the node to tween, the node the behaviour is on if left out
+        property: TweenProperty,
+        duration: f32, // Seconds
+        #[serde(default)]
+        easing: Easing,
+    },
+    Animation {
+        clips: Vec<AnimationClip>,
+        #[serde(default)]
+        playing: Option<String>, // Name of the clip to start playing right away
+        #[serde(default = "normal_speed")]
+        speed: f32,
+    },
+}
+
+fn normal_speed() -> f32 {
+    1.0
+}
+
+fn door_easing() -> Easing {
+    Easing::Smooth
+}
+
+fn default_path_size() -> f32 {
+    FigureEight::default().size
+}
+
+fn default_circuit_speed() -> f32 {
+    FigureEight::default().speed
+}
+
+impl BehaviourDescription {
+    // Catch what can be read from a file but would not work, like a route that can't be flown
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            BehaviourDescription::Waypoints { waypoints, looping, .. } => check_waypoints(waypoints, *looping),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn create(&self) -> Box<dyn Behaviour> {
+        match self {
+            BehaviourDescription::PathFollow { time_offset, size, speed, altitude } => Box::new(PathFollowBehaviour {
+                path: FigureEight { size: *size, speed: *speed, altitude: *altitude, phase: *time_offset },
+            }),
+            BehaviourDescription::FlightSequence { sequence, start_time } => Box::new(FlightSequenceBehaviour::new(*sequence, *start_time)),
+            BehaviourDescription::FlightPath { points, spline, closed, speed, distance_offset } => {
+                let path = FlightPath::new(points.iter().map(|point| glm::make_vec3(point)).collect(), *spline, *closed);
+                Box::new(FlightPathBehaviour { path, speed: *speed, distance_offset: *distance_offset })
+            },
+            BehaviourDescription::Waypoints { waypoints, looping, start_time } => Box::new(WaypointBehaviour {
+                flight: WaypointFlight::new(waypoints.clone(), *looping),
+                start_time: *start_time,
+            }),
+            BehaviourDescription::Spin { axis, rpm } => Box::new(SpinBehaviour::new(glm::make_vec3(axis), *rpm)),
+            BehaviourDescription::Rotors { main_rotor, main_axis, tail_rotor, tail_axis, target_rpm, spin_up, spin_down, gear_ratio, state } => {
+                Box::new(RotorBehaviour {
+                    rotor: Rotor::new(*target_rpm, *spin_up, *spin_down, *gear_ratio, *state),
+                    main_rotor: main_rotor.clone(),
+                    main_axis: glm::make_vec3(main_axis),
+                    tail_rotor: tail_rotor.clone(),
+                    tail_axis: glm::make_vec3(tail_axis),
+                })
+            },
+            BehaviourDescription::Door { door, hinge, axis, open_angle, open_time, easing, open } => {
+                Box::new(DoorBehaviour::new(door, glm::make_vec3(hinge), glm::make_vec3(axis), *open_angle, *open_time, *easing, *open))
+            },
+            BehaviourDescription::Formation { leader, slots, parameters } => Box::new(FormationBehaviour::new(leader, slots.clone(), *parameters)),
+            BehaviourDescription::Flock { tag, parameters } => Box::new(FlockBehaviour::new(tag, *parameters)),
+            BehaviourDescription::Tween { node, property, duration, easing } => property.create(node, *duration, *easing),
+            BehaviourDescription::Animation { clips, playing, speed } => {
+                let mut player = AnimationPlayer::new(clips.clone(), *speed);
+                if let Some(name) = playing {
+                    if !player.play(name) {
+                        println!("There is no animation clip named {}", name);
+                    }
+                }
+                Box::new(player)
+            },
+        }
+    }
+}
+
+// Spin about an axis through the node's reference point at a constant speed, from no rotation at time zero
+pub struct SpinBehaviour {
+    pub axis: glm::Vec3,
+    pub rpm: f32,
+}
+
+impl SpinBehaviour {
+    pub fn new(axis: glm::Vec3, rpm: f32) -> SpinBehaviour {
+        SpinBehaviour { axis, rpm }
+    }
+}
+
+impl Behaviour for SpinBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        let turns = (self.rpm as f64 / 60.0 * ctx.time as f64).fract() as f32; //Only the last part of a turn, so the angle keeps its precision
+        node.set_axis_angle(&self.axis, turns * 2.0 * std::f32::consts::PI);
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::Spin { axis: self.axis.into(), rpm: self.rpm })
+    }
+}
+
+// Put the node where a heading says, facing the way it says
+pub fn apply_heading(node: &mut SceneNode, heading: &Heading) {
+    node.set_euler(&glm::vec3(heading.pitch, heading.yaw, heading.roll), EulerOrder::Yxz); //Yaw outermost, so pitch and roll are relative to the heading
+    node.position = glm::vec3(heading.x, heading.y, heading.z);
+}
+
+// Fly around a figure eight
+pub struct PathFollowBehaviour {
+    pub path: FigureEight,
+}
+
+impl Behaviour for PathFollowBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        apply_heading(node, &self.path.heading(ctx.time));
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::PathFollow {
+            time_offset: self.path.phase,
+            size: self.path.size,
+            speed: self.path.speed,
+            altitude: self.path.altitude,
+        })
+    }
+}
+
+// Take off, fly somewhere and land, start_time seconds after the simulation starts. The door is closed and the
+// rotors are started for take off, if they are not already running, and after landing the rotors are stopped and
+// the door is opened.
+pub struct FlightSequenceBehaviour {
+    pub sequence: FlightSequence,
+    pub start_time: f32,
+    flying: Option<bool>, // Whether we were flying last update, None before the first one
+}
+
+impl FlightSequenceBehaviour {
+    pub fn new(sequence: FlightSequence, start_time: f32) -> FlightSequenceBehaviour {
+        FlightSequenceBehaviour { sequence, start_time, flying: None }
+    }
+}
+
+impl Behaviour for FlightSequenceBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        let time = ctx.time - self.start_time;
+        apply_heading(node, &self.sequence.heading(time));
+
+        let flying = time >= 0.0 && time < self.sequence.duration();
+        if self.flying.is_some() && self.flying != Some(flying) {
+            if flying {
+                node.send_command(Command::CloseDoor);
+                node.send_command(Command::StartRotors);
+            } else {
+                node.send_command(Command::StopRotors);
+                node.send_command(Command::OpenDoor);
+            }
+        }
+        self.flying = Some(flying);
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::FlightSequence { sequence: self.sequence, start_time: self.start_time })
+    }
+}
+
+// Fly along a FlightPath at a constant speed, banking as much as a real aircraft would in the turns
+pub struct FlightPathBehaviour {
+    pub path: FlightPath,
+    pub speed: f32,
+    pub distance_offset: f32,
+}
+
+impl Behaviour for FlightPathBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        let distance = self.distance_offset + self.speed * ctx.time;
+        apply_heading(node, &self.path.heading_at(distance, self.speed));
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::FlightPath {
+            points: self.path.control_points().iter().map(|&point| point.into()).collect(),
+            spline: self.path.kind(),
+            closed: self.path.is_closed(),
+            speed: self.speed,
+            distance_offset: self.distance_offset,
+        })
+    }
+}
@@ -0,0 +1,40 @@
+extern crate nalgebra_glm as glm;
+
+use crate::frustum::transform_point;
+
+// Follows behind and above a node, looking at it. The camera lags a little behind, so it swings around smoothly
+// when the node turns.
+pub struct ChaseCamera {
+    pub distance: f32, // How far behind the node to stay
+    pub height: f32, // How far above the node to stay
+    pub stiffness: f32, // How quickly the camera catches up, per second
+    eye: Option<glm::Vec3>,
+}
+
+impl ChaseCamera {
+    pub fn new(distance: f32, height: f32, stiffness: f32) -> ChaseCamera {
+        ChaseCamera { distance, height, stiffness, eye: None }
+    }
+
+    // Move the camera towards where it should be behind a node with the given world matrix, and return the view
+    // matrix. The view matrix is a usual glm matrix, not in the row vector convention
+    pub fn update(&mut self, world_matrix: &glm::Mat4, delta_time: f32) -> glm::Mat4 {
+        let target = transform_point(world_matrix, &glm::zero());
+        let forward = transform_point(world_matrix, &glm::vec3(0.0, 0.0, -1.0)) - target; //Models face down the negative z-axis
+        let forward = glm::vec3(forward.x, 0.0, forward.z); //Stay level when the node pitches and rolls
+        let forward = if glm::length(&forward) > 1e-6 { glm::normalize(&forward) } else { glm::vec3(0.0, 0.0, -1.0) };
+
+        let wanted = target - forward * self.distance + glm::vec3(0.0, self.height, 0.0);
+        let eye = match self.eye {
+            Some(eye) => glm::lerp(&eye, &wanted, (self.stiffness * delta_time).min(1.0)),
+            None => wanted,
+        };
+        self.eye = Some(eye);
+        glm::look_at(&eye, &target, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    // Snap to the right place next update instead of catching up
+    pub fn reset(&mut self) {
+        self.eye = None;
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Saving what was drawn as PNG files. Screenshots are named after when they were taken, and recordings and
+// --headless write numbered image sequences that video tools can turn into a video, e.g.
+//   ffmpeg -framerate 30 -i frame_%05d.png video.mp4
+
+// Read back what was drawn into the bound framebuffer, or the back buffer of the window before swapping. OpenGL
+// starts at the bottom row and images at the top one, so the rows are flipped
+pub unsafe fn read_pixels(width: u32, height: u32) -> image::RgbImage {
+    let mut pixels = vec![0u8; (width * height * 3) as usize];
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(0, 0, width as i32, height as i32, gl::RGB, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut _);
+    let image = image::RgbImage::from_raw(width, height, pixels).unwrap();
+    image::imageops::flip_vertical(&image)
+}
+
+// The current time in UTC as 2026-10-19_14-03-27-512, down to the millisecond so screenshots taken in the same
+// second get different names, and so that names sort by when they were made
+pub fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, seconds) = ((now.as_secs() / 86400) as i64, now.as_secs() % 86400);
+    let (year, month, day) = date_from_days(days);
+    format!("{:04}-{:02}-{:02}_{:02}-{:02}-{:02}-{:03}", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60, now.subsec_millis())
+}
+
+// Year, month and day of the given number of days since 1970-01-01, from Howard Hinnant's civil_from_days
+fn date_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+// Save a screenshot in directory as screenshot_<timestamp>.png, and return where it went
+pub fn save_screenshot(image: &image::RgbImage, directory: &str) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(directory).map_err(|e| format!("Failed to create {}: {}", directory, e))?;
+    let path = Path::new(directory).join(format!("screenshot_{}.png", timestamp()));
+    image.save(&path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+// Writes every frame it is given to frame_00000.png, frame_00001.png and so on in its directory. The frames are
+// meant to be frame_time apart in simulated time, however long they take to draw and save
+pub struct FrameRecorder {
+    pub directory: PathBuf,
+    pub frame_time: f32,
+    pub frame_count: u32, // Frames saved so far
+}
+
+impl FrameRecorder {
+    pub fn new(directory: &str, frame_rate: f32) -> Result<FrameRecorder, String> {
+        std::fs::create_dir_all(directory).map_err(|e| format!("Failed to create {}: {}", directory, e))?;
+        Ok(FrameRecorder { directory: PathBuf::from(directory), frame_time: 1.0 / frame_rate, frame_count: 0 })
+    }
+
+    pub fn save(&mut self, image: &image::RgbImage) -> Result<(), String> {
+        let path = self.directory.join(format!("frame_{:05}.png", self.frame_count));
+        image.save(&path).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        self.frame_count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_to_dates() {
+        assert_eq!(date_from_days(0), (1970, 1, 1));
+        assert_eq!(date_from_days(-1), (1969, 12, 31));
+        assert_eq!(date_from_days(11016), (2000, 2, 29));
+        assert_eq!(date_from_days(11017), (2000, 3, 1));
+        assert_eq!(date_from_days(20088), (2024, 12, 31));
+        assert_eq!(date_from_days(20089), (2025, 1, 1));
+    }
+}
@@ -0,0 +1,234 @@
+use crate::behaviour::FrameContext;
+
+// Simulation time, which can be paused, sped up, slowed down, stepped and moved to any point. The clock never
+// looks at the real time itself, it is told how much has passed every frame, so it can be driven by made up
+// frame times as well as by the render loop.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    pub step_time: f32, // How long a single step is when stepping frame by frame while paused
+    time: f32,
+    scale: f32,
+    paused: bool,
+    steps: u32, // Frames to step forward while paused
+    seeked: bool, // Whether the time jumped since the last tick
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock { step_time: 1.0 / 60.0, time: 0.0, scale: 1.0, paused: false, steps: 0, seeked: false }
+    }
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock::default()
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    // How many times faster than real time the simulation runs. Negative scales are not allowed
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale.max(0.0);
+    }
+
+    // Move one frame forward at the next tick, even if paused
+    pub fn step(&mut self) {
+        self.steps += 1;
+    }
+
+    // Jump to the given time. Nothing moves smoothly across the jump, the next tick has no delta time
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.max(0.0);
+        self.seeked = true;
+    }
+
+    // Advance the clock by real_delta_time seconds of real time, and say where that leaves the simulation
+    pub fn tick(&mut self, real_delta_time: f32) -> FrameContext {
+        let delta_time = if self.seeked {
+            0.0
+        } else if self.paused {
+            self.steps as f32 * self.step_time
+        } else {
+            real_delta_time * self.scale
+        };
+        let seeked = self.seeked;
+        self.steps = 0;
+        self.seeked = false;
+        self.time += delta_time;
+        FrameContext { time: self.time, delta_time, seeked }
+    }
+}
+
+// Splits simulation time into steps of the same length, so the simulation does the same thing whatever the frame
+// rate is. Whatever is left over after the last whole step is carried over to the next frame, and can be used to
+// draw the scene part of the way between the last two steps.
+pub struct FixedTimestep {
+    pub step_time: f32,
+    pub max_steps: u32, // Most steps to take in one frame. After a long hiccup the simulation skips ahead instead of catching up
+    time: f32, // Simulation time after the last step
+    accumulator: f32, // Time that has passed since the last step
+}
+
+impl FixedTimestep {
+    pub fn new(tick_rate: f32, max_steps: u32) -> FixedTimestep {
+        FixedTimestep { step_time: 1.0 / tick_rate, max_steps, time: 0.0, accumulator: 0.0 }
+    }
+
+    // The steps to take to catch up with a frame from Clock::tick. When the clock has been seeked this becomes a
+    // single step of no length to the new time.
+    pub fn advance(&mut self, frame: &FrameContext) -> Vec<FrameContext> {
+        if frame.seeked {
+            self.time = frame.time;
+            self.accumulator = 0.0;
+            return vec![FrameContext { time: self.time, delta_time: 0.0, seeked: true }];
+        }
+
+        self.accumulator += frame.delta_time;
+        let mut steps = vec![];
+        while self.accumulator >= self.step_time && steps.len() < self.max_steps as usize {
+            self.accumulator -= self.step_time;
+            self.time += self.step_time;
+            steps.push(FrameContext { time: self.time, delta_time: self.step_time, seeked: false });
+        }
+        if steps.len() == self.max_steps as usize && self.accumulator >= self.step_time {
+            //Give up on catching up, and skip the time that is left
+            self.time = frame.time;
+            self.accumulator = 0.0;
+        }
+        steps
+    }
+
+    // How far between the last two steps the current frame is, from 0 to 1
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step_time).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate nalgebra_glm as glm;
+
+    use super::*;
+    use crate::behaviour::{Behaviour, Command, SpinBehaviour};
+    use crate::door::DoorBehaviour;
+    use crate::scene_graph::SceneNode;
+    use crate::tween::Easing;
+
+    #[test]
+    fn ticks_are_scaled() {
+        let mut clock = Clock::new();
+        assert_eq!(clock.tick(0.5), FrameContext { time: 0.5, delta_time: 0.5, seeked: false });
+        clock.set_scale(2.0);
+        assert_eq!(clock.tick(0.25), FrameContext { time: 1.0, delta_time: 0.5, seeked: false });
+        clock.set_scale(-1.0);
+        assert_eq!(clock.scale(), 0.0);
+        assert_eq!(clock.tick(0.25).time, 1.0);
+    }
+
+    #[test]
+    fn paused_clocks_only_step() {
+        let mut clock = Clock { step_time: 0.25, ..Clock::new() };
+        clock.toggle_pause();
+        assert!(clock.is_paused());
+        assert_eq!(clock.tick(1.0).delta_time, 0.0);
+        clock.step();
+        clock.step();
+        assert_eq!(clock.tick(1.0), FrameContext { time: 0.5, delta_time: 0.5, seeked: false });
+        assert_eq!(clock.tick(1.0).time, 0.5); //The steps are used up
+        clock.toggle_pause();
+        assert_eq!(clock.tick(1.0).time, 1.5);
+    }
+
+    #[test]
+    fn seeking_makes_one_frame_without_delta_time() {
+        let mut clock = Clock::new();
+        clock.tick(1.0);
+        clock.seek(10.0);
+        assert_eq!(clock.tick(1.0), FrameContext { time: 10.0, delta_time: 0.0, seeked: true });
+        assert_eq!(clock.tick(1.0), FrameContext { time: 11.0, delta_time: 1.0, seeked: false });
+        clock.seek(-5.0);
+        assert_eq!(clock.tick(1.0).time, 0.0);
+    }
+
+    fn frame(time: f32, delta_time: f32) -> FrameContext {
+        FrameContext { time, delta_time, seeked: false }
+    }
+
+    #[test]
+    fn fixed_steps_carry_over_what_is_left() {
+        let mut timestep = FixedTimestep::new(10.0, 5);
+        assert!(timestep.advance(&frame(0.05, 0.05)).is_empty());
+        assert!((timestep.alpha() - 0.5).abs() < 1e-5);
+
+        let steps = timestep.advance(&frame(0.27, 0.22));
+        assert_eq!(steps.len(), 2);
+        assert!(steps.iter().all(|step| step.delta_time == 0.1 && !step.seeked));
+        assert!((steps[1].time - 0.2).abs() < 1e-5);
+        assert!((timestep.alpha() - 0.7).abs() < 1e-4);
+    }
+
+    #[test]
+    fn fixed_steps_skip_ahead_after_a_hiccup() {
+        let mut timestep = FixedTimestep::new(10.0, 5);
+        assert_eq!(timestep.advance(&frame(2.0, 2.0)).len(), 5);
+        assert_eq!(timestep.alpha(), 0.0);
+        let steps = timestep.advance(&frame(2.1, 0.1));
+        assert_eq!(steps.len(), 1);
+        assert!((steps[0].time - 2.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fixed_steps_follow_seeks() {
+        let mut timestep = FixedTimestep::new(10.0, 5);
+        timestep.advance(&frame(0.15, 0.15));
+        let steps = timestep.advance(&FrameContext { time: 7.0, delta_time: 0.0, seeked: true });
+        assert_eq!(steps, vec![FrameContext { time: 7.0, delta_time: 0.0, seeked: true }]);
+        assert_eq!(timestep.alpha(), 0.0);
+        assert_eq!(timestep.advance(&frame(7.1, 0.1)).len(), 1);
+    }
+
+    #[test]
+    fn seeking_lands_behaviours_where_playing_would() {
+        let (mut played, mut seeked) = (SceneNode::new(), SceneNode::new());
+        let (mut played_spin, mut seeked_spin) = (SpinBehaviour::new(glm::vec3(0.0, 1.0, 0.0), 7.0), SpinBehaviour::new(glm::vec3(0.0, 1.0, 0.0), 7.0));
+
+        let mut clock = Clock::new();
+        for _ in 0..300 {
+            played_spin.update(&mut played, &clock.tick(0.01));
+        }
+        let mut seeking = Clock::new();
+        seeking.seek(clock.time());
+        seeked_spin.update(&mut seeked, &seeking.tick(0.01));
+        assert!((glm::quat_dot(&played.orientation, &seeked.orientation).abs() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn doors_finish_moving_when_seeking() {
+        let (mut node, mut door) = (SceneNode::new(), SceneNode::new());
+        door.name = "door".to_string();
+        node.add_child(&door);
+        let mut behaviour = DoorBehaviour::new("door", glm::zero(), glm::vec3(0.0, 1.0, 0.0), 1.5, 2.0, Easing::Linear, false);
+
+        let mut clock = Clock::new();
+        behaviour.command(Command::OpenDoor);
+        behaviour.update(&mut node, &clock.tick(0.5));
+        assert_eq!(behaviour.angle(), 0.375);
+        clock.seek(0.0);
+        behaviour.update(&mut node, &clock.tick(0.5));
+        assert_eq!(behaviour.angle(), 1.5);
+    }
+}
@@ -0,0 +1,71 @@
+extern crate nalgebra_glm as glm;
+
+use crate::behaviour::{Behaviour, BehaviourDescription, Command, FrameContext};
+use crate::scene_graph::SceneNode;
+use crate::tween::Easing;
+
+// Swings a door node open and closed about a hinge. The door is found by its path relative to the node this is
+// attached to, and the hinge is given in the door's model space.
+pub struct DoorBehaviour {
+    pub door: String,
+    pub hinge: glm::Vec3,
+    pub axis: glm::Vec3,
+    pub open_angle: f32, // Radians
+    pub open_time: f32, // Seconds it takes to open or close all the way
+    pub easing: Easing,
+    open: bool, // Whether the door is opening or closing
+    progress: f32, // 0 when closed, 1 when open
+}
+
+impl DoorBehaviour {
+    pub fn new(door: &str, hinge: glm::Vec3, axis: glm::Vec3, open_angle: f32, open_time: f32, easing: Easing, open: bool) -> DoorBehaviour {
+        DoorBehaviour {
+            door: door.to_string(),
+            hinge, axis, open_angle, open_time, easing, open,
+            progress: if open { 1.0 } else { 0.0 },
+        }
+    }
+
+    // The angle the door is at right now
+    pub fn angle(&self) -> f32 {
+        self.easing.apply(self.progress) * self.open_angle
+    }
+}
+
+impl Behaviour for DoorBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        //After a jump in time the door has finished opening or closing
+        if ctx.seeked {
+            self.progress = if self.open { 1.0 } else { 0.0 };
+        }
+        let step = if self.open_time > 0.0 { ctx.delta_time / self.open_time } else { 1.0 };
+        self.progress = if self.open { (self.progress + step).min(1.0) } else { (self.progress - step).max(0.0) };
+
+        let angle = self.angle();
+        if let Some(door) = node.find(&self.door) {
+            door.reference_point = self.hinge;
+            door.set_axis_angle(&self.axis, angle);
+        }
+    }
+
+    fn command(&mut self, command: Command) {
+        match command {
+            Command::OpenDoor => self.open = true,
+            Command::CloseDoor => self.open = false,
+            Command::ToggleDoor => self.open = !self.open,
+            _ => {},
+        }
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::Door {
+            door: self.door.clone(),
+            hinge: self.hinge.into(),
+            axis: self.axis.into(),
+            open_angle: self.open_angle,
+            open_time: self.open_time,
+            easing: self.easing,
+            open: self.open,
+        })
+    }
+}
@@ -0,0 +1,190 @@
+extern crate nalgebra_glm as glm;
+
+use std::rc::Rc;
+
+use crate::behaviour::{Behaviour, Command, FrameContext};
+use crate::scene_graph::SceneNode;
+use crate::terrain::Terrain;
+
+const GRAVITY: f32 = 9.81;
+
+// What the pilot is asking for. Every control goes from -1 to 1 and is 0 when let go
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FlightInput {
+    pub collective: f32, // Climb, more lift than needed to hover
+    pub forward: f32, // Cyclic, tips the nose down to fly forward
+    pub right: f32, // Cyclic, banks to the right to fly sideways
+    pub turn: f32, // Tail rotor, turns to the right
+}
+
+// A simple helicopter: a point mass lifted along its rotor axis, with gravity and drag pulling on it. The cyclic
+// tilts it towards an attitude instead of setting how fast it rotates, like a stability augmentation system would,
+// which makes it a lot easier to fly. Positions are in the space of the helicopter's parent, with y up.
+#[derive(Clone, Copy, Debug)]
+pub struct FlightModel {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+    pub orientation: glm::Quat,
+    pub angular_velocity: glm::Vec3, // Radians per second about the helicopter's own axes
+    pub ground_clearance: f32, // How far the origin of the helicopter is above the bottom of its skids
+    pub landed: bool,
+
+    pub max_tilt: f32, // Radians of pitch and roll at full cyclic
+    pub max_turn_rate: f32, // Radians per second of yaw at full pedal
+    pub collective_lift: f32, // Extra lift at full collective, as a fraction of the lift needed to hover
+    pub linear_drag: f32,
+    pub quadratic_drag: f32,
+    pub ground_friction: f32, // How fast sliding on the ground stops, per second
+}
+
+impl FlightModel {
+    pub fn new(position: glm::Vec3, orientation: glm::Quat) -> FlightModel {
+        FlightModel {
+            position,
+            velocity: glm::zero(),
+            orientation,
+            angular_velocity: glm::zero(),
+            ground_clearance: 0.0,
+            landed: false,
+            max_tilt: 0.35,
+            max_turn_rate: 1.2,
+            collective_lift: 0.6,
+            linear_drag: 0.1,
+            quadratic_drag: 0.01,
+            ground_friction: 4.0,
+        }
+    }
+
+    // Pitch (nose up) and roll (bank left) relative to level flight
+    pub fn attitude(&self) -> (f32, f32) {
+        let up = glm::quat_rotate_vec3(&glm::quat_inverse(&self.orientation), &glm::vec3(0.0, 1.0, 0.0)); //World up, as seen by the helicopter
+        ((-up.z).atan2(up.y), up.x.atan2(up.y))
+    }
+
+    // Simulate delta_time seconds. ground is the height of the terrain below the helicopter, if there is any
+    pub fn step(&mut self, input: &FlightInput, delta_time: f32, ground: Option<f32>) {
+        let clamp = |value: f32| value.clamp(-1.0, 1.0);
+        let (pitch, roll) = self.attitude();
+
+        //Rotation. Springs towards the attitude the cyclic asks for, and the pedals set the turn rate
+        let (stiffness, damping, yaw_response) = (30.0, 10.0, 4.0);
+        let (target_pitch, target_roll) = if self.landed { (0.0, 0.0) } else { (-clamp(input.forward) * self.max_tilt, -clamp(input.right) * self.max_tilt) };
+        let target_yaw_rate = -clamp(input.turn) * self.max_turn_rate;
+        let angular_acceleration = glm::vec3(
+            stiffness * (target_pitch - pitch) - damping * self.angular_velocity.x,
+            yaw_response * (target_yaw_rate - self.angular_velocity.y),
+            stiffness * (target_roll - roll) - damping * self.angular_velocity.z,
+        );
+        self.angular_velocity += angular_acceleration * delta_time;
+        let angle = glm::length(&self.angular_velocity) * delta_time;
+        if angle > 0.0 {
+            let turn = glm::quat_angle_axis(angle, &glm::normalize(&self.angular_velocity));
+            self.orientation = glm::quat_normalize(&(self.orientation * turn));
+        }
+
+        //Movement. The lift is made stronger when tilted so tilting does not make the helicopter sink
+        let up = glm::quat_rotate_vec3(&self.orientation, &glm::vec3(0.0, 1.0, 0.0));
+        let lift = GRAVITY * (1.0 + clamp(input.collective) * self.collective_lift) / up.y.max(0.5);
+        let speed = glm::length(&self.velocity);
+        let acceleration = up * lift
+            - glm::vec3(0.0, GRAVITY, 0.0)
+            - self.velocity * (self.linear_drag + self.quadratic_drag * speed);
+        self.velocity += acceleration * delta_time;
+        self.position += self.velocity * delta_time;
+
+        //Ground collision. Resting on the ground stops any falling, and slows down sliding
+        self.landed = false;
+        if let Some(ground) = ground {
+            let lowest = ground + self.ground_clearance;
+            if self.position.y <= lowest {
+                self.position.y = lowest;
+                self.velocity.y = self.velocity.y.max(0.0);
+                let friction = (1.0 - self.ground_friction * delta_time).max(0.0);
+                self.velocity.x *= friction;
+                self.velocity.z *= friction;
+                self.landed = true;
+            }
+        }
+    }
+}
+
+// Flies the node it is attached to with a FlightModel, steered by Command::Pilot
+pub struct PilotBehaviour {
+    pub model: Option<FlightModel>, // Made from the node the first time it is updated
+    pub input: FlightInput,
+    pub terrain: Option<Rc<Terrain>>,
+}
+
+impl PilotBehaviour {
+    pub fn new(terrain: Option<Rc<Terrain>>) -> PilotBehaviour {
+        PilotBehaviour { model: None, input: FlightInput::default(), terrain }
+    }
+}
+
+impl Behaviour for PilotBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        let model = self.model.get_or_insert_with(|| {
+            let mut model = FlightModel::new(node.position, node.orientation);
+            model.ground_clearance = node.bounds.map_or(0.0, |bounds| -bounds.min.y); //Rest on the bottom of the mesh
+            model
+        });
+        let ground = self.terrain.as_ref().and_then(|terrain| terrain.height_at(model.position.x, model.position.z));
+        model.step(&self.input, ctx.delta_time, ground);
+        node.position = model.position;
+        node.orientation = model.orientation;
+    }
+
+    fn command(&mut self, command: Command) {
+        if let Command::Pilot(input) = command {
+            self.input = input;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flies the model for the given number of seconds at 60 steps per second
+    fn fly(model: &mut FlightModel, input: &FlightInput, seconds: f32, ground: Option<f32>) {
+        for _ in 0..(seconds * 60.0) as usize {
+            model.step(input, 1.0 / 60.0, ground);
+        }
+    }
+
+    #[test]
+    fn hovers_without_input() {
+        let mut model = FlightModel::new(glm::vec3(0.0, 50.0, 0.0), glm::quat_identity());
+        fly(&mut model, &FlightInput::default(), 10.0, Some(0.0));
+        assert!(glm::distance(&model.position, &glm::vec3(0.0, 50.0, 0.0)) < 1e-3, "{:?}", model.position);
+        assert!(glm::length(&model.velocity) < 1e-3);
+        assert!(!model.landed);
+    }
+
+    #[test]
+    fn forward_cyclic_flies_along_minus_z() {
+        let mut model = FlightModel::new(glm::vec3(0.0, 50.0, 0.0), glm::quat_identity());
+        fly(&mut model, &FlightInput { forward: 1.0, ..Default::default() }, 3.0, None);
+        assert!(model.velocity.z < -5.0 && model.position.z < -5.0, "{:?} {:?}", model.position, model.velocity);
+        assert!(model.velocity.x.abs() < 1e-3);
+        assert!((model.position.y - 50.0).abs() < 2.0); //Tilting does not make it sink
+
+        let (pitch, roll) = model.attitude();
+        assert!((pitch + model.max_tilt).abs() < 0.01 && roll.abs() < 1e-3); //Nose down
+    }
+
+    #[test]
+    fn ground_stops_falling() {
+        let mut model = FlightModel::new(glm::vec3(0.0, 0.6, 0.0), glm::quat_identity());
+        model.ground_clearance = 0.5;
+        model.velocity = glm::vec3(3.0, -5.0, 0.0);
+        model.step(&FlightInput { collective: -1.0, ..Default::default() }, 0.1, Some(0.0));
+        assert!(model.landed);
+        assert_eq!((model.position.y, model.velocity.y), (0.5, 0.0));
+        assert!(model.velocity.x > 0.0 && model.velocity.x < 3.0);
+
+        //Climbing off the ground is not held back
+        model.step(&FlightInput { collective: 1.0, ..Default::default() }, 0.1, Some(0.0));
+        assert!(!model.landed && model.position.y > 0.5);
+    }
+}
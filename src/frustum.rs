@@ -0,0 +1,240 @@
+extern crate nalgebra_glm as glm;
+
+// View-frustum culling. Nothing in here touches OpenGL, so it can be used (and checked) without a context.
+//
+// Matrices in this renderer are applied to row vectors (`vec4(position, 1.0) * matrix` in simple.vert), which makes
+// them the transpose of the usual column-vector glm matrices. Everything below takes matrices in that same convention.
+
+// Transform a point by a row-vector convention matrix, i.e. [p, 1] * m
+pub fn transform_point(m: &glm::Mat4, p: &glm::Vec3) -> glm::Vec3 {
+    let v = glm::transpose(m) * glm::vec4(p.x, p.y, p.z, 1.0);
+    glm::vec3(v.x, v.y, v.z)
+}
+
+// A plane a*x + b*y + c*z + d = 0, with the normal (a, b, c) pointing into the frustum
+#[derive(Clone, Copy, Debug)]
+pub struct Plane {
+    pub normal: glm::Vec3,
+    pub d: f32,
+}
+
+impl Plane {
+    pub fn new(a: f32, b: f32, c: f32, d: f32) -> Plane {
+        let length = glm::length(&glm::vec3(a, b, c));
+        Plane {
+            normal: glm::vec3(a, b, c) / length,
+            d: d / length,
+        }
+    }
+
+    // Signed distance from the plane, positive on the inside
+    pub fn distance(&self, p: &glm::Vec3) -> f32 {
+        glm::dot(&self.normal, p) + self.d
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingSphere {
+    pub center: glm::Vec3,
+    pub radius: f32,
+}
+
+// Axis aligned bounding box
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingBox {
+    pub min: glm::Vec3,
+    pub max: glm::Vec3,
+}
+
+impl BoundingBox {
+    // Bounds of a flat [x, y, z, x, y, z, ...] vertex list, like the ones in mesh::Mesh
+    pub fn from_vertices(vertices: &[f32]) -> Option<BoundingBox> {
+        let mut points = vertices.chunks_exact(3).map(|v| glm::vec3(v[0], v[1], v[2]));
+        let first = points.next()?;
+        Some(points.fold(BoundingBox { min: first, max: first }, |b, p| b.including(&p)))
+    }
+
+    pub fn including(&self, p: &glm::Vec3) -> BoundingBox {
+        BoundingBox {
+            min: glm::min2(&self.min, p),
+            max: glm::max2(&self.max, p),
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: glm::min2(&self.min, &other.min),
+            max: glm::max2(&self.max, &other.max),
+        }
+    }
+
+    pub fn corners(&self) -> [glm::Vec3; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            glm::vec3(a.x, a.y, a.z), glm::vec3(b.x, a.y, a.z),
+            glm::vec3(a.x, b.y, a.z), glm::vec3(b.x, b.y, a.z),
+            glm::vec3(a.x, a.y, b.z), glm::vec3(b.x, a.y, b.z),
+            glm::vec3(a.x, b.y, b.z), glm::vec3(b.x, b.y, b.z),
+        ]
+    }
+
+    // The axis aligned box around this box after transforming it by m
+    pub fn transformed(&self, m: &glm::Mat4) -> BoundingBox {
+        let corners = self.corners();
+        let first = transform_point(m, &corners[0]);
+        corners[1..].iter().fold(BoundingBox { min: first, max: first }, |b, c| b.including(&transform_point(m, c)))
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere {
+            center: (self.min + self.max) * 0.5,
+            radius: glm::distance(&self.min, &self.max) * 0.5,
+        }
+    }
+}
+
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Extract the six clipping planes from a view projection matrix (Gribb & Hartmann).
+    // With row vectors clip = [p, 1] * m, so clip.x is the dot product with the first column of m, and so on.
+    pub fn from_view_projection(m: &glm::Mat4) -> Frustum {
+        let column = |i: usize| glm::vec4(m[(0, i)], m[(1, i)], m[(2, i)], m[(3, i)]);
+        let (x, y, z, w) = (column(0), column(1), column(2), column(3));
+        let plane = |v: glm::Vec4| Plane::new(v.x, v.y, v.z, v.w);
+        Frustum {
+            planes: [
+                plane(w + x), // left
+                plane(w - x), // right
+                plane(w + y), // bottom
+                plane(w - y), // top
+                plane(w + z), // near
+                plane(w - z), // far
+            ],
+        }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| plane.distance(&sphere.center) >= -sphere.radius)
+    }
+
+    // Tests the corner of the box furthest along each plane normal, so this can report boxes that are
+    // just outside a frustum corner as visible, but never the other way around
+    pub fn intersects_box(&self, b: &BoundingBox) -> bool {
+        self.planes.iter().all(|plane| {
+            let furthest = glm::vec3(
+                if plane.normal.x >= 0.0 { b.max.x } else { b.min.x },
+                if plane.normal.y >= 0.0 { b.max.y } else { b.min.y },
+                if plane.normal.z >= 0.0 { b.max.z } else { b.min.z },
+            );
+            plane.distance(&furthest) >= 0.0
+        })
+    }
+
+    // Sphere first since it is cheaper, then the tighter box test
+    pub fn intersects(&self, b: &BoundingBox) -> bool {
+        self.intersects_sphere(&b.bounding_sphere()) && self.intersects_box(b)
+    }
+}
+
+// Number of drawable nodes that were drawn and culled in a frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CullingStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    fn approx_plane(plane: &Plane, normal: [f32; 3], d: f32) -> bool {
+        approx(plane.normal.x, normal[0]) && approx(plane.normal.y, normal[1]) && approx(plane.normal.z, normal[2]) && approx(plane.d, d)
+    }
+
+    // A camera at the origin looking down -z, seeing x and y from -1 to 1 between z = -1 and z = -10.
+    // glm gives column-vector matrices, so it is transposed like every matrix the renderer uses
+    fn box_frustum() -> Frustum {
+        Frustum::from_view_projection(&glm::transpose(&glm::ortho(-1.0, 1.0, -1.0, 1.0, 1.0, 10.0)))
+    }
+
+    fn perspective_frustum() -> Frustum {
+        Frustum::from_view_projection(&glm::transpose(&glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 1.0, 100.0)))
+    }
+
+    fn cube(center: [f32; 3], half_size: f32) -> BoundingBox {
+        let c = glm::make_vec3(&center);
+        BoundingBox { min: c.add_scalar(-half_size), max: c.add_scalar(half_size) }
+    }
+
+    #[test]
+    fn planes_of_an_orthographic_projection() {
+        let planes = box_frustum().planes;
+        assert!(approx_plane(&planes[0], [1.0, 0.0, 0.0], 1.0)); // x >= -1
+        assert!(approx_plane(&planes[1], [-1.0, 0.0, 0.0], 1.0)); // x <= 1
+        assert!(approx_plane(&planes[2], [0.0, 1.0, 0.0], 1.0)); // y >= -1
+        assert!(approx_plane(&planes[3], [0.0, -1.0, 0.0], 1.0)); // y <= 1
+        assert!(approx_plane(&planes[4], [0.0, 0.0, -1.0], -1.0)); // z <= -1
+        assert!(approx_plane(&planes[5], [0.0, 0.0, 1.0], 10.0)); // z >= -10
+    }
+
+    #[test]
+    fn planes_of_a_perspective_projection() {
+        //A 90 degree field of view, so the side planes are at 45 degrees
+        let planes = perspective_frustum().planes;
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(approx_plane(&planes[0], [s, 0.0, -s], 0.0));
+        assert!(approx_plane(&planes[1], [-s, 0.0, -s], 0.0));
+        assert!(approx_plane(&planes[2], [0.0, s, -s], 0.0));
+        assert!(approx_plane(&planes[3], [0.0, -s, -s], 0.0));
+        assert!(approx_plane(&planes[4], [0.0, 0.0, -1.0], -1.0));
+        assert!(approx(planes[5].normal.z, 1.0) && approx(planes[5].d / 100.0, 1.0)); // Loses some precision to cancellation
+    }
+
+    #[test]
+    fn sphere_checks() {
+        let frustum = perspective_frustum();
+        let sphere = |center: [f32; 3], radius| BoundingSphere { center: glm::make_vec3(&center), radius };
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, -10.0], 1.0))); // In front
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 0.0, 10.0], 1.0))); // Behind
+        assert!(!frustum.intersects_sphere(&sphere([0.0, 0.0, -200.0], 1.0))); // Past the far plane
+        assert!(!frustum.intersects_sphere(&sphere([20.0, 0.0, -10.0], 1.0))); // Off to the right
+        assert!(frustum.intersects_sphere(&sphere([10.5, 0.0, -10.0], 1.0))); // Sticking in from the right
+        assert!(frustum.intersects_sphere(&sphere([0.0, 0.0, 0.0], 2.0))); // Around the camera
+    }
+
+    #[test]
+    fn box_checks() {
+        let frustum = box_frustum();
+        assert!(frustum.intersects_box(&cube([0.0, 0.0, -5.0], 0.5))); // Inside
+        assert!(frustum.intersects_box(&cube([1.2, 0.0, -5.0], 0.5))); // Across the right plane
+        assert!(frustum.intersects_box(&cube([0.0, 0.0, -5.0], 50.0))); // Around the whole frustum
+        assert!(!frustum.intersects_box(&cube([2.0, 0.0, -5.0], 0.5))); // Right of it
+        assert!(!frustum.intersects_box(&cube([0.0, -2.0, -5.0], 0.5))); // Below it
+        assert!(!frustum.intersects_box(&cube([0.0, 0.0, 1.0], 0.5))); // Behind the camera
+        assert!(!frustum.intersects_box(&cube([0.0, 0.0, -12.0], 0.5))); // Past the far plane
+    }
+
+    #[test]
+    fn combined_check_rejects_what_the_sphere_lets_through() {
+        //Near a frustum corner the sphere around a long thin box reaches inside, but the box itself doesn't
+        let frustum = box_frustum();
+        let thin = BoundingBox { min: glm::vec3(1.1, -3.0, -5.1), max: glm::vec3(1.2, 3.0, -4.9) };
+        assert!(frustum.intersects_sphere(&thin.bounding_sphere()));
+        assert!(!frustum.intersects(&thin));
+        assert!(frustum.intersects(&cube([0.0, 0.0, -5.0], 0.5)));
+    }
+
+    #[test]
+    fn transformed_box_follows_the_matrix() {
+        //Row vectors, so the translation is in the bottom row
+        let moved = cube([0.0, 0.0, 0.0], 1.0).transformed(&glm::transpose(&glm::translation(&glm::vec3(5.0, 0.0, -2.0))));
+        assert!(approx(moved.min.x, 4.0) && approx(moved.max.x, 6.0) && approx(moved.min.z, -3.0) && approx(moved.max.z, -1.0));
+    }
+}
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+
+// Golden image tests, run with --golden golden/cases.ron. Every case renders a scene headlessly from a fixed camera
+// and compares it with a reference image that was rendered earlier and checked in, in the reference directory next
+// to the cases file. A case fails when too many pixels differ by more than the tolerance, or when the images look
+// too different as a whole. Small differences between drivers and machines are expected, so both have some slack.
+//
+// Run with --update-golden to write new reference images after changing how things look on purpose. CI runs the
+// cases through tests/golden.rs, with cargo test --test golden -- --ignored
+
+fn default_size() -> (u32, u32) {
+    (320, 240)
+}
+
+fn default_instancing() -> bool {
+    true
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tolerance {
+    pub pixel: u8, // Largest difference in any color channel for a pixel to still count as the same
+    pub differing_pixels: f32, // Fraction of pixels allowed to differ by more than that
+    pub min_similarity: f32, // Lowest structural similarity (SSIM) allowed between the images, 1 is identical
+}
+
+impl Default for Tolerance {
+    fn default() -> Tolerance {
+        Tolerance { pixel: 8, differing_pixels: 0.001, min_similarity: 0.98 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GoldenCase {
+    pub name: String, // Also the name of the reference image, <name>.png
+    pub scene: String,
+    #[serde(default)]
+    pub mission: Option<String>,
+    pub eye: [f32; 3], // Where the camera is
+    pub target: [f32; 3], // What it looks at
+    #[serde(default = "default_size")]
+    pub size: (u32, u32),
+    #[serde(default)]
+    pub time: f32, // Seconds of simulation before the picture is taken
+    #[serde(default = "default_instancing")]
+    pub instancing: bool,
+    #[serde(default)]
+    pub tolerance: Tolerance,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GoldenCases {
+    pub cases: Vec<GoldenCase>,
+}
+
+pub fn load(path: &str) -> Result<GoldenCases, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read golden cases {}: {}", path, e))?;
+    ron::de::from_str(&text).map_err(|e| format!("Failed to parse golden cases {}: {}", path, e))
+}
+
+// How a rendered image differs from its reference
+pub struct Comparison {
+    pub differing_pixels: u32, // Pixels that differ by more than the tolerance
+    pub largest_difference: u8,
+    pub similarity: f32,
+    pub diff: image::RgbImage, // The reference dimmed to grey, with the pixels that differ too much in red
+}
+
+impl Comparison {
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        let pixel_count = self.diff.width() * self.diff.height();
+        self.differing_pixels as f32 <= tolerance.differing_pixels * pixel_count as f32 && self.similarity >= tolerance.min_similarity
+    }
+}
+
+pub fn compare(reference: &image::RgbImage, actual: &image::RgbImage, tolerance: &Tolerance) -> Result<Comparison, String> {
+    if reference.dimensions() != actual.dimensions() {
+        return Err(format!("the image is {:?} but the reference is {:?}", actual.dimensions(), reference.dimensions()));
+    }
+
+    let mut differing_pixels = 0;
+    let mut largest_difference = 0;
+    let diff = image::RgbImage::from_fn(reference.width(), reference.height(), |x, y| {
+        let (a, b) = (reference.get_pixel(x, y), actual.get_pixel(x, y));
+        let difference = (0..3).map(|c| (a[c] as i32 - b[c] as i32).unsigned_abs() as u8).max().unwrap();
+        largest_difference = largest_difference.max(difference);
+        if difference > tolerance.pixel {
+            differing_pixels += 1;
+            image::Rgb([difference.max(128), 0, 0]) //Brighter the more it differs
+        } else {
+            let grey = (luma(a) / 3.0) as u8;
+            image::Rgb([grey, grey, grey])
+        }
+    });
+
+    Ok(Comparison { differing_pixels, largest_difference, similarity: similarity(reference, actual), diff })
+}
+
+fn luma(pixel: &image::Rgb<u8>) -> f32 {
+    0.299 * pixel[0] as f32 + 0.587 * pixel[1] as f32 + 0.114 * pixel[2] as f32
+}
+
+// Structural similarity of the brightness of two images of the same size, the mean SSIM of overlapping 8x8
+// windows. Unlike counting differing pixels it notices when the overall look changes, e.g. edges that are blurred
+// or shifted or a change in contrast, and it cares less about noise
+fn similarity(a: &image::RgbImage, b: &image::RgbImage) -> f32 {
+    const WINDOW: u32 = 8;
+    const STRIDE: u32 = 4;
+    const C1: f32 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f32 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = a.dimensions();
+    if width < WINDOW || height < WINDOW {
+        return if a == b { 1.0 } else { 0.0 };
+    }
+
+    let (mut total, mut windows) = (0.0, 0);
+    for top in (0..=height - WINDOW).step_by(STRIDE as usize) {
+        for left in (0..=width - WINDOW).step_by(STRIDE as usize) {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in top..top + WINDOW {
+                for x in left..left + WINDOW {
+                    let (la, lb) = (luma(a.get_pixel(x, y)), luma(b.get_pixel(x, y)));
+                    sum_a += la;
+                    sum_b += lb;
+                    sum_aa += la * la;
+                    sum_bb += lb * lb;
+                    sum_ab += la * lb;
+                }
+            }
+            let n = (WINDOW * WINDOW) as f32;
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let variance_a = sum_aa / n - mean_a * mean_a;
+            let variance_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (variance_a + variance_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A smooth gradient with some detail, so the similarity has edges to look at
+    fn picture(width: u32, height: u32) -> image::RgbImage {
+        image::RgbImage::from_fn(width, height, |x, y| image::Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, if (x / 8 + y / 8) % 2 == 0 { 40 } else { 200 }]))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let reference = picture(64, 48);
+        let comparison = compare(&reference, &reference.clone(), &Tolerance::default()).unwrap();
+        assert_eq!((comparison.differing_pixels, comparison.largest_difference), (0, 0));
+        assert!((comparison.similarity - 1.0).abs() < 1e-4);
+        assert!(comparison.passes(&Tolerance::default()));
+    }
+
+    #[test]
+    fn one_changed_pixel_is_found() {
+        let reference = picture(64, 48);
+        let mut actual = reference.clone();
+        actual.put_pixel(10, 20, image::Rgb([255, 255, 255]));
+        let comparison = compare(&reference, &actual, &Tolerance::default()).unwrap();
+        assert_eq!(comparison.differing_pixels, 1);
+        assert_eq!(comparison.largest_difference, 255 - reference.get_pixel(10, 20).0.iter().min().unwrap());
+        assert_eq!(comparison.diff.get_pixel(10, 20)[1], 0);
+        assert!(comparison.diff.get_pixel(10, 20)[0] >= 128);
+        assert!(comparison.similarity < 1.0 && comparison.similarity > 0.9);
+
+        //The default allows 0.1% of the pixels to differ, which is 3 of these 3072
+        assert!(comparison.passes(&Tolerance::default()));
+        assert!(!comparison.passes(&Tolerance { differing_pixels: 0.0, ..Tolerance::default() }));
+        //Small differences are within the pixel tolerance
+        actual.put_pixel(10, 20, image::Rgb([reference.get_pixel(10, 20)[0] + 8, reference.get_pixel(10, 20)[1], reference.get_pixel(10, 20)[2]]));
+        assert_eq!(compare(&reference, &actual, &Tolerance::default()).unwrap().differing_pixels, 0);
+    }
+
+    #[test]
+    fn changed_contrast_lowers_the_similarity() {
+        let reference = picture(64, 48);
+        let flat = image::RgbImage::from_fn(64, 48, |x, y| {
+            let pixel = reference.get_pixel(x, y);
+            image::Rgb([pixel[0] / 4 + 96, pixel[1] / 4 + 96, pixel[2] / 4 + 96])
+        });
+        assert!(similarity(&reference, &flat) < Tolerance::default().min_similarity);
+    }
+
+    #[test]
+    fn images_of_different_sizes_do_not_compare() {
+        assert!(compare(&picture(64, 48), &picture(48, 64), &Tolerance::default()).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use std::ptr;
+
+use khronos_egl as egl;
+
+// Rendering without a window, for machines without a display or a GPU. The context comes from EGL on Mesa's
+// surfaceless platform, which falls back to llvmpipe when there is no GPU, and everything is drawn into a
+// framebuffer object that is read back into an image.
+
+// EGL_PLATFORM_SURFACELESS_MESA, from the EGL_MESA_platform_surfaceless extension
+const PLATFORM_SURFACELESS_MESA: egl::Enum = 0x31DD;
+
+// An OpenGL 4.3 core context that is current on the thread that made it
+pub struct HeadlessContext {
+    egl: egl::DynamicInstance<egl::EGL1_5>,
+    display: egl::Display,
+    context: egl::Context,
+}
+
+impl HeadlessContext {
+    pub fn new() -> Result<HeadlessContext, String> {
+        //libEGL is loaded when it is needed, so running in a window does not depend on it
+        let library = unsafe { libloading::Library::new("libEGL.so.1") }.map_err(|e| format!("Failed to load libEGL: {}", e))?;
+        let egl = unsafe { egl::DynamicInstance::<egl::EGL1_5>::load_required_from(library) }.map_err(|e| format!("libEGL is older than 1.5: {}", e))?;
+
+        let display = egl.get_platform_display(PLATFORM_SURFACELESS_MESA, egl::DEFAULT_DISPLAY, &[egl::ATTRIB_NONE])
+            .map_err(|e| format!("Failed to get a surfaceless EGL display: {}", e))?;
+        egl.initialize(display).map_err(|e| format!("Failed to initialize EGL: {}", e))?;
+        egl.bind_api(egl::OPENGL_API).map_err(|e| format!("EGL has no OpenGL: {}", e))?;
+
+        let config = egl.choose_first_config(display, &[egl::SURFACE_TYPE, egl::PBUFFER_BIT, egl::RENDERABLE_TYPE, egl::OPENGL_BIT, egl::NONE])
+            .map_err(|e| format!("Failed to choose an EGL config: {}", e))?
+            .ok_or_else(|| "There is no EGL config for OpenGL".to_string())?;
+        let context = egl.create_context(display, config, None, &[
+            egl::CONTEXT_MAJOR_VERSION, 4,
+            egl::CONTEXT_MINOR_VERSION, 3,
+            egl::CONTEXT_OPENGL_PROFILE_MASK, egl::CONTEXT_OPENGL_CORE_PROFILE_BIT,
+            egl::NONE,
+        ]).map_err(|e| format!("Failed to create an OpenGL 4.3 context: {}", e))?;
+        //No surface at all, everything is drawn into framebuffer objects
+        egl.make_current(display, None, None, Some(context)).map_err(|e| format!("Failed to make the context current: {}", e))?;
+
+        gl::load_with(|symbol| egl.get_proc_address(symbol).map_or(ptr::null(), |f| f as *const _));
+        Ok(HeadlessContext { egl, display, context })
+    }
+}
+
+impl Drop for HeadlessContext {
+    fn drop(&mut self) {
+        self.egl.make_current(self.display, None, None, None).ok();
+        self.egl.destroy_context(self.display, self.context).ok();
+        self.egl.terminate(self.display).ok();
+    }
+}
+
+// A framebuffer object with a color and a depth buffer, to draw into instead of a window
+pub struct OffscreenTarget {
+    pub width: u32,
+    pub height: u32,
+    framebuffer: u32,
+}
+
+impl OffscreenTarget {
+    pub unsafe fn new(width: u32, height: u32) -> Result<OffscreenTarget, String> {
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+
+        let mut renderbuffers = [0; 2];
+        gl::GenRenderbuffers(2, renderbuffers.as_mut_ptr());
+        for (&renderbuffer, &(format, attachment)) in renderbuffers.iter().zip(&[(gl::RGBA8, gl::COLOR_ATTACHMENT0), (gl::DEPTH_COMPONENT24, gl::DEPTH_ATTACHMENT)]) {
+            gl::BindRenderbuffer(gl::RENDERBUFFER, renderbuffer);
+            gl::RenderbufferStorage(gl::RENDERBUFFER, format, width as i32, height as i32);
+            gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, renderbuffer);
+        }
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::DeleteRenderbuffers(2, renderbuffers.as_ptr());
+            gl::DeleteFramebuffers(1, &framebuffer);
+            return Err(format!("Failed to make a {}x{} offscreen framebuffer, its status is {:#x}", width, height, status));
+        }
+
+        Ok(OffscreenTarget { width, height, framebuffer })
+    }
+
+    // Draw into this target from now on
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::Viewport(0, 0, self.width as i32, self.height as i32);
+    }
+}
@@ -0,0 +1,80 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::ptr;
+
+// Attribute locations used by the four columns of the per-instance model matrix in instanced.vert
+const INSTANCE_MATRIX_LOCATION: u32 = 6;
+
+// Nodes can be drawn in the same instanced draw call when they use the same VAO, and therefore the same
+// vertex data and vertex colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BatchKey {
+    pub vao_id: u32,
+    pub index_count: i32,
+}
+
+// Model matrices of every visible node, grouped by what they draw. Filled while traversing the scene graph.
+#[derive(Default)]
+pub struct InstanceBatches {
+    pub batches: HashMap<BatchKey, Vec<glm::Mat4>>,
+}
+
+impl InstanceBatches {
+    pub fn push(&mut self, key: BatchKey, model_matrix: glm::Mat4) {
+        self.batches.entry(key).or_default().push(model_matrix);
+    }
+
+    // Keeps the allocated vectors around so they can be reused next frame
+    pub fn clear(&mut self) {
+        for matrices in self.batches.values_mut() {
+            matrices.clear();
+        }
+    }
+}
+
+// Owns one per-instance transform buffer for each VAO that has been drawn instanced
+#[derive(Default)]
+pub struct InstanceRenderer {
+    instance_buffers: HashMap<u32, u32>,
+}
+
+impl InstanceRenderer {
+    // Attach a per-instance mat4 attribute to the given VAO. A mat4 attribute takes up four consecutive locations,
+    // one per column, and the divisor makes each of them advance once per instance instead of once per vertex.
+    unsafe fn setup_instance_buffer(vao_id: u32) -> u32 {
+        gl::BindVertexArray(vao_id);
+
+        let mut buffer_id: u32 = 0;
+        gl::GenBuffers(1, &mut buffer_id);
+        gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+
+        let stride = super::size_of::<glm::Mat4>();
+        for column in 0..4 {
+            let location = INSTANCE_MATRIX_LOCATION + column;
+            gl::VertexAttribPointer(location, 4, gl::FLOAT, gl::FALSE, stride, super::offset::<glm::Vec4>(column));
+            gl::EnableVertexAttribArray(location);
+            gl::VertexAttribDivisor(location, 1);
+        }
+
+        buffer_id
+    }
+
+    // Upload the transforms of each batch and draw it with a single call. Expects the instanced shader to be active.
+    pub unsafe fn draw(&mut self, batches: &InstanceBatches) -> u32 {
+        let mut draw_calls = 0;
+        for (key, matrices) in &batches.batches {
+            if matrices.is_empty() {
+                continue;
+            }
+            let buffer_id = *self.instance_buffers.entry(key.vao_id).or_insert_with(|| InstanceRenderer::setup_instance_buffer(key.vao_id));
+
+            gl::BindVertexArray(key.vao_id);
+            gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);
+            gl::BufferData(gl::ARRAY_BUFFER, super::byte_size_of_array(matrices), super::pointer_to_array(matrices), gl::STREAM_DRAW);
+            gl::DrawElementsInstanced(gl::TRIANGLES, key.index_count, gl::UNSIGNED_INT, ptr::null(), matrices.len() as i32);
+            draw_calls += 1;
+        }
+        draw_calls
+    }
+}
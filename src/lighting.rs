@@ -0,0 +1,221 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{Deserialize, Serialize};
+
+use crate::frustum::transform_point;
+use crate::shader::Shader;
+use crate::shadow::Shadows;
+
+// Most lights simple.frag will light a fragment with. It is passed to the shader as a define when it is compiled.
+// When there are more lights than this, the ones furthest from the camera are left out, see closest_lights
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+    Directional, // Infinitely far away, like the sun. Only the direction matters
+    Point, // Shines the same in every direction from its position
+    Spot { inner_angle: f32, outer_angle: f32 }, // A cone around the direction, fading out between the two angles (radians)
+}
+
+impl LightKind {
+    // Must match the constants in simple.frag
+    fn id(&self) -> i32 {
+        match self {
+            LightKind::Directional => 0,
+            LightKind::Point => 1,
+            LightKind::Spot { .. } => 2,
+        }
+    }
+}
+
+fn full_intensity() -> f32 {
+    1.0
+}
+
+fn straight_down() -> [f32; 3] {
+    [0.0, -1.0, 0.0]
+}
+
+fn no_attenuation() -> [f32; 3] {
+    [1.0, 0.0, 0.0]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    #[serde(default = "full_intensity")]
+    pub intensity: f32,
+    #[serde(default)]
+    pub position: [f32; 3], // In world space, or in the model space of the node it is attached to. Not used by directional lights
+    #[serde(default = "straight_down")]
+    pub direction: [f32; 3], // Which way the light shines, in the same space as the position. Not used by point lights
+    #[serde(default = "no_attenuation")]
+    pub attenuation: [f32; 3], // Constant, linear and quadratic falloff with distance. Not used by directional lights
+}
+
+impl Light {
+    // The same light moved by a model matrix, e.g. from the model space of a node to world space
+    pub fn transformed(&self, matrix: &glm::Mat4) -> Light {
+        let position = transform_point(matrix, &glm::make_vec3(&self.position));
+        let direction = transform_point(matrix, &(glm::make_vec3(&self.position) + glm::make_vec3(&self.direction))) - position;
+        Light { position: position.into(), direction: direction.into(), ..*self }
+    }
+}
+
+// Drop lights until there are at most max_lights left. Directional lights are kept first since they light
+// everything, and then the lights closest to the camera
+pub fn closest_lights(lights: &mut Vec<Light>, camera_position: &glm::Vec3, max_lights: usize) {
+    if lights.len() <= max_lights {
+        return;
+    }
+    let importance = |light: &Light| match light.kind {
+        LightKind::Directional => -1.0,
+        _ => glm::distance(&glm::make_vec3(&light.position), camera_position),
+    };
+    lights.sort_by(|a, b| importance(a).partial_cmp(&importance(b)).unwrap_or(std::cmp::Ordering::Equal));
+    lights.truncate(max_lights);
+}
+
+// Everything that lights the scene, apart from the lights attached to scene nodes. The specular highlights are the
+// same for every mesh
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lighting {
+    pub ambient: [f32; 3], // Light that reaches everything, even where no light shines
+    pub specular: f32, // How strong the specular highlights are, 0 turns them off
+    pub shininess: f32, // Blinn-Phong exponent, higher makes smaller and sharper highlights
+    pub lights: Vec<Light>,
+    pub shadows: Shadows, // Cast by the first directional light
+}
+
+impl Default for Lighting {
+    // The sun that used to be hardcoded in simple.frag
+    fn default() -> Lighting {
+        Lighting {
+            ambient: [0.1, 0.1, 0.1],
+            specular: 0.3,
+            shininess: 32.0,
+            lights: vec![Light {
+                kind: LightKind::Directional,
+                color: [1.0, 1.0, 1.0],
+                intensity: 1.0,
+                position: [0.0; 3],
+                direction: [0.8, -0.5, 0.6],
+                attenuation: no_attenuation(),
+            }],
+            shadows: Shadows::default(),
+        }
+    }
+}
+
+// Uniform locations of one element of the lights array in simple.frag
+struct LightLocations {
+    kind: i32,
+    color: i32,
+    position: i32,
+    direction: i32,
+    attenuation: i32,
+    inner_cutoff: i32,
+    outer_cutoff: i32,
+}
+
+// The lighting uniforms of a shader that uses simple.frag, looked up once so they can be set every frame
+pub struct LightUniforms {
+    light_count: i32,
+    ambient: i32,
+    specular: i32,
+    shininess: i32,
+    camera_position: i32,
+    lights: Vec<LightLocations>,
+}
+
+impl LightUniforms {
+    pub unsafe fn new(shader: &Shader) -> LightUniforms {
+        let location = |name: &str| shader.get_uniform_location(name);
+        LightUniforms {
+            light_count: location("lightCount"),
+            ambient: location("ambientLight"),
+            specular: location("specularStrength"),
+            shininess: location("shininess"),
+            camera_position: location("cameraPosition"),
+            lights: (0..MAX_LIGHTS).map(|i| LightLocations {
+                kind: location(&format!("lights[{}].kind", i)),
+                color: location(&format!("lights[{}].color", i)),
+                position: location(&format!("lights[{}].position", i)),
+                direction: location(&format!("lights[{}].direction", i)),
+                attenuation: location(&format!("lights[{}].attenuation", i)),
+                inner_cutoff: location(&format!("lights[{}].innerCutoff", i)),
+                outer_cutoff: location(&format!("lights[{}].outerCutoff", i)),
+            }).collect(),
+        }
+    }
+
+    // Set the lighting for the next draw calls, lit by the given lights in world space instead of the ones in
+    // lighting. The shader has to be active
+    pub unsafe fn upload(&self, lighting: &Lighting, lights: &[Light], camera_position: &glm::Vec3) {
+        let count = lights.len().min(self.lights.len());
+        gl::Uniform1i(self.light_count, count as i32);
+        gl::Uniform3fv(self.ambient, 1, lighting.ambient.as_ptr());
+        gl::Uniform1f(self.specular, lighting.specular);
+        gl::Uniform1f(self.shininess, lighting.shininess);
+        gl::Uniform3f(self.camera_position, camera_position.x, camera_position.y, camera_position.z);
+
+        for (light, locations) in lights.iter().zip(&self.lights) {
+            let color = glm::make_vec3(&light.color) * light.intensity;
+            let direction = glm::make_vec3(&light.direction);
+            let direction = if glm::length(&direction) > 0.0 { glm::normalize(&direction) } else { glm::vec3(0.0, -1.0, 0.0) };
+            let (inner_angle, outer_angle) = match light.kind {
+                LightKind::Spot { inner_angle, outer_angle } => (inner_angle, outer_angle.max(inner_angle + 1e-3)), //Keep the fade from dividing by zero
+                _ => (0.0, 0.0),
+            };
+            gl::Uniform1i(locations.kind, light.kind.id());
+            gl::Uniform3fv(locations.color, 1, color.as_ptr());
+            gl::Uniform3fv(locations.position, 1, light.position.as_ptr());
+            gl::Uniform3fv(locations.direction, 1, direction.as_ptr());
+            gl::Uniform3fv(locations.attenuation, 1, light.attenuation.as_ptr());
+            gl::Uniform1f(locations.inner_cutoff, inner_angle.cos());
+            gl::Uniform1f(locations.outer_cutoff, outer_angle.cos());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light(kind: LightKind, x: f32) -> Light {
+        Light { kind, color: [1.0, 1.0, 1.0], intensity: 1.0, position: [x, 0.0, 0.0], direction: straight_down(), attenuation: no_attenuation() }
+    }
+
+    #[test]
+    fn closest_lights_keep_directional_lights_first() {
+        let spot = LightKind::Spot { inner_angle: 0.3, outer_angle: 0.5 };
+        let lights = vec![
+            light(LightKind::Point, 50.0),
+            light(LightKind::Directional, 1000.0),
+            light(spot, -5.0),
+            light(LightKind::Point, 20.0),
+            light(LightKind::Directional, 2000.0),
+        ];
+        let camera = glm::vec3(0.0, 0.0, 0.0);
+
+        let mut kept = lights.clone();
+        closest_lights(&mut kept, &camera, 4);
+        assert_eq!(kept, vec![lights[1], lights[4], lights[2], lights[3]]);
+
+        let mut kept = lights.clone();
+        closest_lights(&mut kept, &camera, 1);
+        assert_eq!(kept, vec![lights[1]]);
+
+        //Closest to the camera, wherever it is
+        let mut kept = lights.clone();
+        closest_lights(&mut kept, &glm::vec3(45.0, 0.0, 0.0), 3);
+        assert_eq!(kept, vec![lights[1], lights[4], lights[0]]);
+
+        //Nothing changes when there are few enough
+        let mut kept = lights.clone();
+        closest_lights(&mut kept, &camera, 5);
+        assert_eq!(kept, lights);
+    }
+}
@@ -2,12 +2,34 @@ extern crate nalgebra_glm as glm;
 use std::{ mem, ptr, os::raw::c_void };
 use std::thread;
 use std::sync::{Mutex, Arc, RwLock};
+use std::rc::Rc;
+use std::collections::HashMap;
 
 mod shader;
 mod util;
 mod mesh;
 mod scene_graph;
 mod toolbox;
+mod frustum;
+mod instancing;
+mod scene_file;
+mod prefab;
+mod behaviour;
+mod animation;
+mod rotor;
+mod door;
+mod tween;
+mod clock;
+mod terrain;
+mod flight;
+mod camera;
+mod steering;
+mod mission;
+mod lighting;
+mod shadow;
+mod headless;
+mod capture;
+mod golden;
 
 use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
 use glutin::event_loop::ControlFlow;
@@ -15,10 +37,30 @@ use glutin::event_loop::ControlFlow;
 const SCREEN_W: u32 = 800;
 const SCREEN_H: u32 = 600;
 
+const DEFAULT_SCENE_PATH: &str = "./scenes/lunar.ron";
+const DEFAULT_SAVED_SCENE_PATH: &str = "./scenes/saved.ron";
+
+// Near and far planes of the camera
+const NEAR_PLANE: f32 = 1.0;
+const FAR_PLANE: f32 = 1000.0;
+
+const DEFAULT_TICK_RATE: f32 = 60.0;
+const PLAYER_PATH: &str = "terrain/player"; //Where the helicopter flown with --pilot is in the scene graph
+const MAX_CATCH_UP_STEPS: u32 = 8;
+
+// What --headless renders when nothing else is asked for
+const DEFAULT_HEADLESS_OUTPUT: &str = "./frames";
+const DEFAULT_HEADLESS_CAMERA: [f32; 6] = [0.0, 60.0, 120.0, 0.0, 0.0, 0.0]; // Eye and target
+const DEFAULT_GOLDEN_OUTPUT: &str = "./golden_output"; // Where --golden puts the images that failed
+
+// Where screenshots and recordings go, and how many frames per second of simulated time are recorded, also with --headless
+const DEFAULT_CAPTURE_PATH: &str = "./captures";
+const DEFAULT_FRAME_RATE: f32 = 30.0;
+
 // == // Helper functions to make interacting with OpenGL a little bit prettier. You *WILL* need these! // == //
 // The names should be pretty self explanatory
 fn byte_size_of_array<T>(val: &[T]) -> isize {
-    std::mem::size_of_val(&val[..]) as isize
+    std::mem::size_of_val(val) as isize
 }
 
 // Get the OpenGL-compatible pointer to an arbitrary array of numbers
@@ -43,40 +85,40 @@ fn offset<T>(n: u32) -> *const c_void {
 
 // == // Modify and complete the function below for the first task
 //This function sets up a vertex array object (VAO), it takes two arguments that is the data (vertices) and the indices (used to fill the index buffer to tell which vertices should be connected together) and it returns the VAO ID
-unsafe fn setup_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>, normals: &Vec<f32>) -> u32 { 
+unsafe fn setup_vao(vertices: &[f32], indices: &[u32], colors: &[f32], normals: &[f32]) -> u32 { 
     let mut array: u32 = 0; //a pointer to a location where the generated VAO ID can be stored. since we only are allocating a single VAO I created this empty unsigned int
     gl::GenVertexArrays(1, &mut array); //This will generate a VAO
     gl::BindVertexArray(array); //This will bind the VAO
 
-    let mut bufferID: u32 = 0; //Here the ID of the buffer (VBO) will be stored
-    gl::GenBuffers(1, &mut bufferID); // This will genereate a buffer
-    gl::BindBuffer(gl::ARRAY_BUFFER, bufferID);// this will bind the buffer in the created earlier in last line
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&vertices), pointer_to_array(&vertices), gl::STATIC_DRAW); //Here we will fill the buffer with our data
+    let mut buffer_id: u32 = 0; //Here the ID of the buffer (VBO) will be stored
+    gl::GenBuffers(1, &mut buffer_id); // This will genereate a buffer
+    gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id);// this will bind the buffer in the created earlier in last line
+    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(vertices), pointer_to_array(vertices), gl::STATIC_DRAW); //Here we will fill the buffer with our data
     gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, ptr::null()); //Here we define a format for our buffer (because we didnt tell OpenGL about our data, so it does not know if we passed x,y or x,y,z etc, here we tell it)
     gl::EnableVertexAttribArray(0); //This will enable the pointer. index is same as in previwes line
 
 
     //INDEX BUFFER (index buffer spesifies how the vertecies in databuffer should be combined together, else we wont know which points are connected and not)
-    let mut bufferID2: u32 = 0; //Soter the ID of the buffer
-    gl::GenBuffers(1, &mut bufferID2); //Generate buffer
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, bufferID2);// bind the buffer
-    gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, byte_size_of_array(&indices), pointer_to_array(&indices), gl::STATIC_DRAW);//Fill wil indices
+    let mut buffer_id2: u32 = 0; //Soter the ID of the buffer
+    gl::GenBuffers(1, &mut buffer_id2); //Generate buffer
+    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, buffer_id2);// bind the buffer
+    gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, byte_size_of_array(indices), pointer_to_array(indices), gl::STATIC_DRAW);//Fill wil indices
 
 
     //Color
-    let mut bufferID3: u32 = 0; 
-    gl::GenBuffers(1, &mut bufferID3); //Generate buffer
-    gl::BindBuffer(gl::ARRAY_BUFFER, bufferID3);// bind the buffer
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&colors), pointer_to_array(&colors), gl::STATIC_DRAW);//Fill with colors
+    let mut buffer_id3: u32 = 0; 
+    gl::GenBuffers(1, &mut buffer_id3); //Generate buffer
+    gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id3);// bind the buffer
+    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(colors), pointer_to_array(colors), gl::STATIC_DRAW);//Fill with colors
     gl::VertexAttribPointer(1, 4, gl::FLOAT, gl::FALSE, 0, ptr::null()); //Here we define a format for our buffer (because we didnt tell OpenGL about our data, so it does not know if we passed x,y or x,y,z etc, here we tell it)
     gl::EnableVertexAttribArray(1); //This will enable the pointer. index is same as in previous line
 
 
     //Normal
-    let mut bufferID4: u32 = 0; 
-    gl::GenBuffers(1, &mut bufferID4); //Generate buffer
-    gl::BindBuffer(gl::ARRAY_BUFFER, bufferID4);// bind the buffer
-    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(&normals), pointer_to_array(&normals), gl::STATIC_DRAW);//Fill with normals
+    let mut buffer_id4: u32 = 0; 
+    gl::GenBuffers(1, &mut buffer_id4); //Generate buffer
+    gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id4);// bind the buffer
+    gl::BufferData(gl::ARRAY_BUFFER, byte_size_of_array(normals), pointer_to_array(normals), gl::STATIC_DRAW);//Fill with normals
     gl::VertexAttribPointer(2, 3, gl::FLOAT, gl::FALSE, 0, ptr::null()); //Here we define a format for our buffer (because we didnt tell OpenGL about our data, so it does not know if we passed x,y or x,y,z etc, here we tell it)
     gl::EnableVertexAttribArray(2); //This will enable the pointer. index is same as in previous line
 
@@ -88,56 +130,470 @@ unsafe fn setup_vao(vertices: &Vec<f32>, indices: &Vec<u32>, colors: &Vec<f32>,
     array //At the end we return the VAO ID. We will use this ID to refer to the array whenever we want to do something with it
 } 
 
-unsafe fn draw_scene(root: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4){
+unsafe fn draw_scene(root: &scene_graph::SceneNode, view_projection_matrix: &glm::Mat4, frustum: &frustum::Frustum, stats: &mut frustum::CullingStats){
+
+    //Skip the whole subtree if its bounds are outside the view frustum
+    if let Some(bounds) = &root.world_bounds {
+        if !frustum.intersects(bounds) {
+            stats.culled += root.drawable_count();
+            return;
+        }
+    }
 
     //MVP matirx
     let mvp_matrix: glm::Mat4 = root.current_transformation_matrix * view_projection_matrix;
 
     //Check if node is drawable, set uniforms, draw
-    if(root.index_count > -1){
+    if root.index_count > -1 {
         gl::UniformMatrix4fv(4, 1, gl::FALSE, root.current_transformation_matrix.as_ptr());//Pass the model matrix as a uniform variable to the vertex shader. this will be used to transfrom the vertex normal and fix the lighting as the helicopter turns
         gl::UniformMatrix4fv(5, 1, gl::FALSE, view_projection_matrix.as_ptr());//Pass the view projection matrix as a uniform variable to the vertex shader at location 5, used to transform the input vertext
 
         gl::UniformMatrix4fv(3, 1, gl::FALSE, mvp_matrix.as_ptr());
 
+        //Here I pass the normal matrix, the inverse transpose of the model matrix, so the lighting is right on nodes that are scaled more in one direction than the others
+        let normal_matrix: glm::Mat3 = glm::transpose(&glm::inverse(&glm::mat4_to_mat3(&root.current_transformation_matrix)));
+        gl::UniformMatrix3fv(6, 1, gl::FALSE, normal_matrix.as_ptr());
+
         gl::BindVertexArray(root.vao_id); //bind
         gl::DrawElements(gl::TRIANGLES, root.index_count, gl::UNSIGNED_INT, ptr::null()); //Draw
+        stats.drawn += 1;
     }
 
     for &child in &root.children {
-        draw_scene(&*child, &view_projection_matrix);
+        draw_scene(&*child, view_projection_matrix, frustum, stats);
     }
 }
 
 
-unsafe fn update_node_transformations(root: &mut scene_graph::SceneNode, transformation_so_far: &glm::Mat4){
-    
-    //construct the correct transformation matrix
-    let translationMatrix: glm::Mat4 = glm::translation(&root.position); //This is the translation matrix
-    let transposeTranslation: glm::Mat4 = glm::transpose(&translationMatrix); //Transpose the translation matrix
-
-    let rotationX: glm::Mat4 = glm::rotation(root.rotation[0], &glm::vec3(root.reference_point[0],0.0,0.0)); //Rotate about the x-axis 
-    let rotationY: glm::Mat4 = glm::rotation(root.rotation[1], &glm::vec3(0.0,root.reference_point[1],0.0)); //Rotate about the y-axis 
-    let rotationZ: glm::Mat4 = glm::rotation(root.rotation[2], &glm::vec3(0.0,0.0,root.reference_point[2])); //Rotate about the z-axis 
-    let rotationMatrix: glm::Mat4 = rotationX * rotationY * rotationZ; //Calculate total rotation
-    
+//Same traversal and culling as draw_scene, but instead of drawing each node its model matrix is added to the batch for its VAO
+fn collect_instances(root: &scene_graph::SceneNode, frustum: &frustum::Frustum, stats: &mut frustum::CullingStats, batches: &mut instancing::InstanceBatches){
 
-    /**
-     * I have to first move the rotor to the origin, then apply the rotation, and then move it back to where it was. Movement to the origin is done by translating by a vector which is the inverse of the reference point.
-     * My problem was that I don’t know the function in rust that returns the invers of a point. So it is not rotating about the reference point...
-     */
-    
-    let transformationMatrix: glm::Mat4 = rotationMatrix * transposeTranslation; //Calculate total transformation matrix
+    if let Some(bounds) = &root.world_bounds {
+        if !frustum.intersects(bounds) {
+            stats.culled += root.drawable_count();
+            return;
+        }
+    }
+
+    if root.index_count > -1 {
+        batches.push(instancing::BatchKey { vao_id: root.vao_id, index_count: root.index_count }, root.current_transformation_matrix);
+        stats.drawn += 1;
+    }
 
-    //update the node's transformation matrix
-    root.current_transformation_matrix = transformationMatrix * transformation_so_far;
-    
     for &child in &root.children {
-        update_node_transformations(&mut *child, &root.current_transformation_matrix);
-     }
+        collect_instances(unsafe { &*child }, frustum, stats, batches);
+    }
+}
+
+// The value following a command line flag, e.g. --scene path/to/scene.ron
+fn argument(flag: &str) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|arg| arg == flag)
+        .map(|i| args.get(i + 1).unwrap_or_else(|| panic!("{} expects a value", flag)).clone())
+}
+
+
+// The command line options used both with a window and with --headless
+struct Options {
+    scene_path: String, // The scene to load
+    helicopter_count: Option<usize>,
+    mission: Option<mission::Mission>, // Helicopter routes to fly, see missions/patrol.ron
+    pilot: bool, // Fly a helicopter by hand
+    tick_rate: f32, // Simulation steps per second, the frame rate doesn't change how the simulation runs
+}
+
+// What to render with --headless, and where to put it
+struct HeadlessOptions {
+    frame_count: u32,
+    output: String, // Directory the frames are written to as frame_00000.png, frame_00001.png and so on
+    view: OffscreenView,
+    frame_rate: f32, // Simulated frames per second, the time between frames is the same however long they take to render
+}
+
+// The size of an image rendered without a window, and the camera it is seen through, which stays put
+struct OffscreenView {
+    width: u32,
+    height: u32,
+    eye: glm::Vec3, // Where the camera is
+    target: glm::Vec3, // What it looks at
+}
+
+// A comma separated list of count numbers following a command line flag, e.g. --camera 0,60,120,0,0,0
+fn numbers_argument(flag: &str, count: usize) -> Option<Vec<f32>> {
+    argument(flag).map(|text| {
+        let numbers: Vec<f32> = text.split(',').map(|n| n.trim().parse().unwrap_or_else(|_| panic!("{} expects {} numbers separated by commas", flag, count))).collect();
+        if numbers.len() != count {
+            panic!("{} expects {} numbers separated by commas", flag, count);
+        }
+        numbers
+    })
+}
+
+//Here I set up openGL, the context has to be current on this thread
+unsafe fn setup_gl() {
+    gl::Enable(gl::DEPTH_TEST);
+    gl::DepthFunc(gl::LESS);
+    gl::Enable(gl::CULL_FACE);
+    gl::Disable(gl::MULTISAMPLE);
+    gl::Enable(gl::BLEND);
+    gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+    gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+    gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
+
+    // Print some diagnostics
+    println!("{}: {}", util::get_gl_string(gl::VENDOR), util::get_gl_string(gl::RENDERER));
+    println!("OpenGL\t: {}", util::get_gl_string(gl::VERSION));
+    println!("GLSL\t: {}", util::get_gl_string(gl::SHADING_LANGUAGE_VERSION));
+}
+
+// The scene graph, with the helicopters asked for on the command line, and the scene file it was made from
+struct World {
+    description: scene_file::SceneDescription,
+    meshes: HashMap<String, mesh::MeshHandle>,
+    root: scene_graph::Node,
+}
+
+fn load_world(options: &Options) -> World {
+    //Here I load the scene file, and the meshes it uses. As mentioned earlier setup_vao returns the array ID which I have to use later to draw the primitive
+    let scene_description = scene_file::load(&options.scene_path).unwrap_or_else(|e| panic!("{}", e));
+    let mut mesh_cache = mesh::MeshCache::default();
+    let scene = scene_description.build(|source, material| {
+        let mesh = mesh_cache.load(&source.path, source.object.as_deref(), material.color);
+        let vao_id = unsafe { setup_vao(&mesh.vertices, &mesh.indices, &mesh.colors, &mesh.normals) };
+        mesh::MeshHandle { vao_id, index_count: mesh.index_count, bounds: mesh.bounds }
+    }).unwrap_or_else(|e| panic!("{}", e));
+
+    //Here I get the scene graph built from the scene file
+    let mut root_scene_node = scene.root;
+
+    //Here I add more helicopters if asked to with --helicopters N, each following the one before it by 0.8 seconds
+    if let Some(count) = options.helicopter_count {
+        let helicopter = scene.prefabs.get("helicopter").expect("The scene has no helicopter prefab to make more helicopters from");
+        let existing = root_scene_node.find_by_tag("helicopter").len();
+        let terrain_scene_node = root_scene_node.find("terrain").expect("The scene has no terrain to add helicopters to");
+        for x in existing..count {
+            let mut instance = helicopter.instantiate(terrain_scene_node);
+            instance.root().name = format!("heli_{}", x);
+            instance.root().behaviours.push(Box::new(behaviour::PathFollowBehaviour {
+                path: toolbox::FigureEight { phase: 0.8 * x as f32, ..Default::default() },
+            }));
+        }
+    }
+
+    //Here I put the helicopters of the mission on their routes, if one was given with --mission. Helicopters that
+    //are not in the scene are made from the helicopter prefab
+    if let Some(mission) = &options.mission {
+        for route in &mission.routes {
+            if root_scene_node.find(&route.helicopter).is_none() {
+                let (parent_path, name) = route.helicopter.rsplit_once('/').unwrap_or(("", &route.helicopter));
+                let helicopter = scene.prefabs.get("helicopter").expect("The scene has no helicopter prefab to make the mission's helicopters from");
+                let parent = root_scene_node.find(parent_path).unwrap_or_else(|| panic!("The scene has no {} to put {} in", parent_path, name));
+                helicopter.instantiate(parent).root().name = name.to_string();
+            }
+            let helicopter_scene_node = root_scene_node.find(&route.helicopter).unwrap();
+            helicopter_scene_node.behaviours.push(route.behaviour().create());
+        }
+    }
+
+    //Here I add a helicopter to fly by hand if asked to with --pilot. It starts on the ground in the middle of the
+    //terrain, and collides with the terrain mesh
+    if options.pilot {
+        let terrain = scene_description.meshes.get("terrain")
+            .map(|source| Rc::new(terrain::Terrain::from_mesh(&mesh_cache.load(&source.path, source.object.as_deref(), [1.0; 4]))));
+        let helicopter = scene.prefabs.get("helicopter").expect("The scene has no helicopter prefab to fly");
+        let terrain_scene_node = root_scene_node.find("terrain").expect("The scene has no terrain to fly over");
+        let mut instance = helicopter.instantiate(terrain_scene_node);
+        let player = instance.root();
+        player.name = PLAYER_PATH.rsplit('/').next().unwrap().to_string();
+        player.position.y = terrain.as_ref().and_then(|terrain| terrain.height_at(0.0, 0.0)).unwrap_or(0.0);
+        player.behaviours.push(Box::new(flight::PilotBehaviour::new(terrain)));
+    }
+
+    World { description: scene_description, meshes: scene.meshes, root: root_scene_node }
+}
+
+//Here I animate. Each node runs the behaviours attached to it, which are set in the scene file, once for every
+//simulation step in the frame. Then the node transformations are updated, with nodes placed between their last two
+//simulation steps so they move smoothly whatever the frame rate. Returns how many transformations were recomputed
+fn simulate(root: &mut scene_graph::SceneNode, fixed_timestep: &mut clock::FixedTimestep, frame: &behaviour::FrameContext) -> u32 {
+    for step in fixed_timestep.advance(frame) {
+        root.store_previous_transforms();
+        root.update_behaviours(&step);
+        if step.seeked {
+            root.store_previous_transforms(); //Jumped in time, so there is nothing to blend between
+        }
+    }
+    root.update_transformations(&glm::identity(), false, fixed_timestep.alpha()) //Only nodes that moved, or whose parent moved, are recomputed
+}
+
+// The shaders, and everything else that is kept between frames to draw the scene graph with its lights and shadows
+struct Renderer {
+    shader: shader::Shader,
+    instanced_shader: shader::Shader,
+    shadow_shader: shader::Shader,
+    shader_lights: lighting::LightUniforms,
+    instanced_shader_lights: lighting::LightUniforms,
+    shader_shadows: shadow::ShadowUniforms,
+    instanced_shader_shadows: shadow::ShadowUniforms,
+    shadow_map: Option<shadow::ShadowMap>,
+    shadow_batches: instancing::InstanceBatches,
+    instance_batches: instancing::InstanceBatches,
+    instance_renderer: instancing::InstanceRenderer,
+    lighting: lighting::Lighting,
+}
+
+impl Renderer {
+    unsafe fn new(scene_lighting: &lighting::Lighting) -> Renderer {
+        //Here I load the shaders, the vertex shader and the fragment shader then they are linked.
+        let shader = shader::ShaderBuilder::new().define("MAX_LIGHTS", &lighting::MAX_LIGHTS.to_string()).attach_file("./shaders/simple.vert").attach_file("./shaders/simple.frag").link();
+
+        //Same fragment shader, but the vertex shader takes the model matrix as a per-instance attribute
+        let instanced_shader = shader::ShaderBuilder::new().define("MAX_LIGHTS", &lighting::MAX_LIGHTS.to_string()).attach_file("./shaders/instanced.vert").attach_file("./shaders/simple.frag").link();
+
+        //Here I set up shadows. The scene is drawn from the light into the shadow map with a depth only shader, and
+        //both of the other shaders look up shadows in it
+        let shadow_shader = shader::ShaderBuilder::new().attach_file("./shaders/shadow.vert").attach_file("./shaders/shadow.frag").link();
+        let shadow_map = if scene_lighting.shadows.enabled { Some(shadow::ShadowMap::new(scene_lighting.shadows.resolution)) } else { None };
+
+        //Here I look up where the lights and shadows go in both shaders. The lights themselves come from the scene
+        //file, some of them attached to scene nodes
+        Renderer {
+            shader_lights: lighting::LightUniforms::new(&shader),
+            instanced_shader_lights: lighting::LightUniforms::new(&instanced_shader),
+            shader_shadows: shadow::ShadowUniforms::new(&shader),
+            instanced_shader_shadows: shadow::ShadowUniforms::new(&instanced_shader),
+            shader,
+            instanced_shader,
+            shadow_shader,
+            shadow_map,
+            shadow_batches: instancing::InstanceBatches::default(),
+            instance_batches: instancing::InstanceBatches::default(),
+            instance_renderer: instancing::InstanceRenderer::default(),
+            lighting: scene_lighting.clone(),
+        }
+    }
+
+    // Clear the bound framebuffer and draw the scene graph in it, as seen through view_projection from
+    // camera_position. Returns how many nodes were drawn and culled, and how many draw calls it took
+    unsafe fn draw(&mut self, root_scene_node: &scene_graph::SceneNode, transformation_combo: &glm::Mat4, camera_position: &glm::Vec3, use_instancing: bool) -> (frustum::CullingStats, u32) {
+        let Renderer { shader, instanced_shader, shadow_shader, shader_lights, instanced_shader_lights, shader_shadows, instanced_shader_shadows, shadow_map, shadow_batches, instance_batches, instance_renderer, lighting: scene_lighting } = self;
+
+        gl::ClearColor(0.163, 0.163, 0.163, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+        //Here I gather the lights. The ones on nodes follow them around, so they are collected after the update
+        let mut frame_lights = scene_lighting.lights.clone();
+        root_scene_node.collect_lights(&mut frame_lights);
+        lighting::closest_lights(&mut frame_lights, camera_position, lighting::MAX_LIGHTS);
+
+        //Here I draw the shadow map from the first directional light, around what the camera sees. Shadow casters
+        //are always drawn instanced, and culled by what the light sees
+        let shadow_caster = match shadow_map {
+            Some(shadow_map) => frame_lights.iter().position(|light| light.kind == lighting::LightKind::Directional).map(|index| {
+                let light_space = shadow::fit_light_space(transformation_combo, camera_position, NEAR_PLANE, &scene_lighting.shadows, &glm::make_vec3(&frame_lights[index].direction), root_scene_node.world_bounds.as_ref());
+                shadow_batches.clear();
+                collect_instances(root_scene_node, &frustum::Frustum::from_view_projection(&light_space.view_projection), &mut frustum::CullingStats::default(), shadow_batches);
+                shadow_map.begin();
+                shadow_shader.activate();
+                gl::UniformMatrix4fv(5, 1, gl::FALSE, light_space.view_projection.as_ptr());
+                instance_renderer.draw(shadow_batches);
+                shadow_map.end();
+                shadow_map.bind_texture();
+                (index, light_space)
+            }),
+            None => None,
+        };
+        let shadow_caster = shadow_caster.as_ref().map(|(index, light_space)| (*index, light_space));
+
+        //Here I cull everything outside the camera's view frustum while drawing
+        let view_frustum = frustum::Frustum::from_view_projection(transformation_combo);
+        let mut stats = frustum::CullingStats::default();
+        let draw_calls = if use_instancing {
+            instance_batches.clear();
+            collect_instances(root_scene_node, &view_frustum, &mut stats, instance_batches);
+
+            instanced_shader.activate();
+            instanced_shader_lights.upload(scene_lighting, &frame_lights, camera_position);
+            instanced_shader_shadows.upload(&scene_lighting.shadows, shadow_caster);
+            gl::UniformMatrix4fv(5, 1, gl::FALSE, transformation_combo.as_ptr());
+            instance_renderer.draw(instance_batches)
+        } else {
+            shader.activate();
+            shader_lights.upload(scene_lighting, &frame_lights, camera_position);
+            shader_shadows.upload(&scene_lighting.shadows, shadow_caster);
+            draw_scene(root_scene_node, transformation_combo, &view_frustum, &mut stats);
+            stats.drawn
+        };
+        (stats, draw_calls)
+    }
+}
+
+//Here I render the scene without a window, into a framebuffer object. The simulation is moved forward by each of
+//the delta times in turn, and every frame drawn after that is passed to save. A headless context has to be current
+fn render_offscreen<F>(options: &Options, view: &OffscreenView, use_instancing: bool, delta_times: &[f32], mut save: F) -> Result<(), String>
+    where F: FnMut(image::RgbImage) -> Result<(), String>
+{
+    let target = unsafe { headless::OffscreenTarget::new(view.width, view.height)? };
+    let mut world = load_world(options);
+    let mut renderer = unsafe { Renderer::new(&world.description.lighting) };
+
+    let projection: glm::Mat4 = glm::perspective(view.width as f32 / view.height as f32, 1.00, NEAR_PLANE, FAR_PLANE);
+    let view_matrix = glm::transpose(&glm::look_at(&view.eye, &view.target, &glm::vec3(0.0, 1.0, 0.0)));
+    let transformation_combo = view_matrix * projection;
+
+    //Every node starts where its behaviours put it at time zero, so the first frame is not the scene file's layout
+    let mut clock = clock::Clock::new();
+    let mut fixed_timestep = clock::FixedTimestep::new(options.tick_rate, u32::MAX);
+    world.root.update_behaviours(&behaviour::FrameContext::default());
+    world.root.store_previous_transforms();
+
+    for &delta_time in delta_times {
+        simulate(&mut world.root, &mut fixed_timestep, &clock.tick(delta_time));
+        let image = unsafe {
+            target.bind();
+            renderer.draw(&world.root, &transformation_combo, &view.eye, use_instancing);
+            capture::read_pixels(view.width, view.height)
+        };
+        save(image)?;
+    }
+    Ok(())
+}
+
+//Here I render without a window, into PNG files, when asked to with --headless N. The scene is drawn on an EGL
+//context, so it works on servers with no display or GPU
+fn run_headless(options: &Options, headless: &HeadlessOptions) -> Result<(), String> {
+    let _context = headless::HeadlessContext::new()?;
+    unsafe { setup_gl() };
+    let mut recorder = capture::FrameRecorder::new(&headless.output, headless.frame_rate)?;
+
+    //The first frame is at time zero
+    let delta_times: Vec<f32> = (0..headless.frame_count).map(|frame| if frame == 0 { 0.0 } else { recorder.frame_time }).collect();
+    render_offscreen(options, &headless.view, true, &delta_times, |image| recorder.save(&image))?;
+    println!("Wrote {} frames to {}", headless.frame_count, headless.output);
+    Ok(())
+}
+
+//Here I run the golden image tests in cases_path, see golden.rs. Images that do not match their reference are
+//written to output, with a diff image next to them. With update the rendered images become the new references
+//instead. Returns whether every case passed
+fn run_golden(options: &Options, cases_path: &str, output: &str, update: bool) -> Result<bool, String> {
+    let suite = golden::load(cases_path)?;
+    let reference_directory = std::path::Path::new(cases_path).with_file_name("reference");
+    let _context = headless::HeadlessContext::new()?;
+    unsafe { setup_gl() };
+
+    let mut failures = 0;
+    for case in &suite.cases {
+        let case_options = Options {
+            scene_path: case.scene.clone(),
+            helicopter_count: None,
+            mission: case.mission.as_ref().map(|path| mission::load(path)).transpose()?,
+            pilot: false,
+            tick_rate: options.tick_rate,
+        };
+        let view = OffscreenView { width: case.size.0, height: case.size.1, eye: glm::make_vec3(&case.eye), target: glm::make_vec3(&case.target) };
+        let mut rendered = None;
+        render_offscreen(&case_options, &view, case.instancing, &[0.0, case.time], |image| {
+            rendered = Some(image);
+            Ok(())
+        }).map_err(|e| format!("Failed to render {}: {}", case.name, e))?;
+        let rendered = rendered.unwrap();
+
+        let reference_path = reference_directory.join(format!("{}.png", case.name));
+        if update {
+            std::fs::create_dir_all(&reference_directory).map_err(|e| format!("Failed to create {}: {}", reference_directory.display(), e))?;
+            rendered.save(&reference_path).map_err(|e| format!("Failed to write {}: {}", reference_path.display(), e))?;
+            println!("UPDATED {}", case.name);
+            continue;
+        }
+
+        //A missing or unreadable reference fails the case, instead of stopping the other cases
+        let result = image::open(&reference_path)
+            .map_err(|e| format!("no reference image {} ({}), run with --update-golden to make one", reference_path.display(), e))
+            .and_then(|reference| golden::compare(&reference.to_rgb(), &rendered, &case.tolerance));
+        let failure = match &result {
+            Ok(comparison) if comparison.passes(&case.tolerance) => None,
+            Ok(comparison) => Some(format!("{} pixels differ by more than {}, up to {}, similarity {:.4}",
+                comparison.differing_pixels, case.tolerance.pixel, comparison.largest_difference, comparison.similarity)),
+            Err(e) => Some(e.clone()),
+        };
+        match failure {
+            None => println!("PASS {}", case.name),
+            Some(reason) => {
+                failures += 1;
+                println!("FAIL {}: {}", case.name, reason);
+                std::fs::create_dir_all(output).map_err(|e| format!("Failed to create {}: {}", output, e))?;
+                let actual_path = std::path::Path::new(output).join(format!("{}.png", case.name));
+                rendered.save(&actual_path).map_err(|e| format!("Failed to write {}: {}", actual_path.display(), e))?;
+                if let Ok(comparison) = &result {
+                    let diff_path = std::path::Path::new(output).join(format!("{}_diff.png", case.name));
+                    comparison.diff.save(&diff_path).map_err(|e| format!("Failed to write {}: {}", diff_path.display(), e))?;
+                }
+            },
+        }
+    }
+
+    if !update {
+        println!("{} of {} golden image tests passed", suite.cases.len() - failures, suite.cases.len());
+    }
+    Ok(failures == 0)
 }
 
 fn main() {
+    // The scene to load, and where to save it when pressing F5
+    let options = Options {
+        scene_path: argument("--scene").unwrap_or_else(|| DEFAULT_SCENE_PATH.to_string()),
+        helicopter_count: argument("--helicopters").map(|n| n.parse().expect("--helicopters expects a number")),
+        mission: argument("--mission").map(|path| mission::load(&path).unwrap_or_else(|e| panic!("{}", e))),
+        pilot: std::env::args().any(|arg| arg == "--pilot"),
+        tick_rate: argument("--tick-rate").map_or(DEFAULT_TICK_RATE, |n| n.parse().expect("--tick-rate expects a number")),
+    };
+    let saved_scene_path = argument("--save-scene").unwrap_or_else(|| DEFAULT_SAVED_SCENE_PATH.to_string());
+    // Where F12 saves screenshots and F10 recordings, and the frame rate of recordings
+    let capture_path = argument("--capture-dir").unwrap_or_else(|| DEFAULT_CAPTURE_PATH.to_string());
+    let frame_rate: f32 = argument("--frame-rate").map_or(DEFAULT_FRAME_RATE, |n| n.parse().expect("--frame-rate expects a number"));
+    // Start recording right away, instead of waiting for F10
+    let record = std::env::args().any(|arg| arg == "--record");
+
+    // Render N frames to PNG files instead of opening a window
+    if let Some(frame_count) = argument("--headless") {
+        let size = argument("--size").map(|size| {
+            let (width, height) = size.split_once('x').expect("--size expects WIDTHxHEIGHT");
+            (width.parse().expect("--size expects WIDTHxHEIGHT"), height.parse().expect("--size expects WIDTHxHEIGHT"))
+        });
+        let camera = numbers_argument("--camera", 6).unwrap_or_else(|| DEFAULT_HEADLESS_CAMERA.to_vec());
+        let headless = HeadlessOptions {
+            frame_count: frame_count.parse().expect("--headless expects a number of frames"),
+            output: argument("--output").unwrap_or_else(|| DEFAULT_HEADLESS_OUTPUT.to_string()),
+            view: OffscreenView {
+                width: size.map_or(SCREEN_W, |size| size.0),
+                height: size.map_or(SCREEN_H, |size| size.1),
+                eye: glm::vec3(camera[0], camera[1], camera[2]),
+                target: glm::vec3(camera[3], camera[4], camera[5]),
+            },
+            frame_rate,
+        };
+        if let Err(e) = run_headless(&options, &headless) {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Run the golden image tests in a cases file, e.g. golden/cases.ron, instead of opening a window. Exits with
+    // an error code when any of them fail
+    if let Some(cases_path) = argument("--golden") {
+        let output = argument("--output").unwrap_or_else(|| DEFAULT_GOLDEN_OUTPUT.to_string());
+        let update = std::env::args().any(|arg| arg == "--update-golden");
+        match run_golden(&options, &cases_path, &output, update) {
+            Ok(true) => return,
+            Ok(false) => println!("Images that did not match are in {}", output),
+            Err(e) => println!("{}", e),
+        }
+        std::process::exit(1);
+    }
+
     // Set up the necessary objects to deal with windows and event handling
     let el = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
@@ -172,130 +628,23 @@ fn main() {
         };
 
         // Set up openGL
-        unsafe {
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::Enable(gl::CULL_FACE);
-            gl::Disable(gl::MULTISAMPLE);
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
-            gl::DebugMessageCallback(Some(util::debug_callback), ptr::null());
-
-            // Print some diagnostics
-            println!("{}: {}", util::get_gl_string(gl::VENDOR), util::get_gl_string(gl::RENDERER));
-            println!("OpenGL\t: {}", util::get_gl_string(gl::VERSION));
-            println!("GLSL\t: {}", util::get_gl_string(gl::SHADING_LANGUAGE_VERSION));
-        }
-
-    //Here I load the lunarsurface.obj
-    let terrain_mesh = mesh::Terrain::load("./resources/lunarsurface.obj"); //Load the lunar surface
-
-    //Here I setup the VAOs. As mentioned earlier this returns the array ID which I have to use later to draw the primitive   
-    
-    //Setupt terrain vao
-    let terrain_vao = unsafe {
-        let vertices: Vec<f32> = terrain_mesh.vertices; //Get vertices, indices, colors and normals from terrain mesh 
-        let indices: Vec<u32> = terrain_mesh.indices;
-        let colors: Vec<f32> = terrain_mesh.colors;
-        let normals: Vec<f32> = terrain_mesh.normals;
-        setup_vao(&vertices, &indices, &colors, &normals)    
-    };
-
-
-    let heli_mesh = mesh::Helicopter::load("./resources/helicopter.obj"); //Load helicopter
-
-    let heli_body_vao = unsafe {
-        let vertices: Vec<f32> = heli_mesh.body.vertices;
-        let indices: Vec<u32> = heli_mesh.body.indices;
-        let colors: Vec<f32> = heli_mesh.body.colors;
-        let normals: Vec<f32> = heli_mesh.body.normals;
-        setup_vao(&vertices, &indices, &colors, &normals)    
-    };
-
-
-    let heli_main_rotor_vao = unsafe {
-        let vertices: Vec<f32> = heli_mesh.main_rotor.vertices;
-        let indices: Vec<u32> = heli_mesh.main_rotor.indices;
-        let colors: Vec<f32> = heli_mesh.main_rotor.colors;
-        let normals: Vec<f32> = heli_mesh.main_rotor.normals;
-        setup_vao(&vertices, &indices, &colors, &normals)    
-    };
-
-    let heli_tail_rotor_vao = unsafe {
-        let vertices: Vec<f32> = heli_mesh.tail_rotor.vertices;
-        let indices: Vec<u32> = heli_mesh.tail_rotor.indices;
-        let colors: Vec<f32> = heli_mesh.tail_rotor.colors;
-        let normals: Vec<f32> = heli_mesh.tail_rotor.normals;
-        setup_vao(&vertices, &indices, &colors, &normals)    
-    };
-
-
-    let heli_door_vao = unsafe {
-        let vertices: Vec<f32> = heli_mesh.door.vertices;
-        let indices: Vec<u32> = heli_mesh.door.indices;
-        let colors: Vec<f32> = heli_mesh.door.colors;
-        let normals: Vec<f32> = heli_mesh.door.normals;
-        setup_vao(&vertices, &indices, &colors, &normals)    
-    };
-    
-    // Basic usage of shader helper
-    // The code below returns a shader object, which contains the field .program_id
-    // The snippet is not enough to do the assignment, and will need to be modified (outside of just using the correct path)
-
-    //Here I load the shaders, the vertex shader and the fragment shader then they are linked.
-    let shader = unsafe{
-        shader::ShaderBuilder::new().attach_file("./shaders/simple.vert").attach_file("./shaders/simple.frag").link()
-    };
+        unsafe { setup_gl() };
 
-    //Here I create a scene graph
-    let mut root_scene_node = scene_graph::SceneNode::new();//Generate a root scene node
-    let mut terrain_scene_node = scene_graph::SceneNode::from_vao(terrain_vao, terrain_mesh.index_count);//Generate a scene node for the terrain
-    
-    //Create array of heli_body_node 's
-    let mut heli_bodies = Vec::new();
-    let mut main_rotors = Vec::new();
-    let mut tail_rotors = Vec::new();
-
-    //Here I do a for loop
-    //I push 5 bodyies, main rotors, tail rotors and doors
-    //I init ref points
-    //I add helicopter body as child to terrain node
-    for x in 0..5 {
-        let mut heli_body_node = scene_graph::SceneNode::from_vao(heli_body_vao, heli_mesh.body.index_count);//Generate a scene node for the helicopter body
-        let mut heli_main_rotor_node = scene_graph::SceneNode::from_vao(heli_main_rotor_vao, heli_mesh.main_rotor.index_count);//Generate a scene node for the helicopter body
-        let mut heli_tail_rotor_node = scene_graph::SceneNode::from_vao(heli_tail_rotor_vao, heli_mesh.tail_rotor.index_count);//Generate a scene node for the helicopter body
-        let mut heli_door_node = scene_graph::SceneNode::from_vao(heli_door_vao, heli_mesh.door.index_count);//Generate a scene node for the helicopter body
-        
-        //Set reference point
-        heli_tail_rotor_node.reference_point = glm::vec3(0.35, 2.3, 10.4);
-        heli_body_node.reference_point = glm::vec3(-0.68, -0.19, -4.13); 
-        heli_main_rotor_node.reference_point = glm::vec3(-0.68, -0.19, -4.13); //Since the origin of the helicopters model lines up with the main rotor on the xz-plane, this only has to match the referce point of the helicopter.
-
-        //Add helicopter parts
-        heli_body_node.add_child(&heli_main_rotor_node); //Add main rotor as a child node to helicopter
-        heli_body_node.add_child(&heli_tail_rotor_node); //Add tail rotor as a child node to helicopter
-        heli_body_node.add_child(&heli_door_node); //Add door as a child node to helicopter
-        
-        terrain_scene_node.add_child(&heli_body_node); //Add helicopter body as a child node to terrain node
-
-        //Push helicopter and its parts to it's arrays
-        heli_bodies.push(heli_body_node);
-        main_rotors.push(heli_main_rotor_node);
-        tail_rotors.push(heli_tail_rotor_node);
-    }
-
-    //heli_body_node.position = glm::vec3(0.5, 0.5, 0.0); //Set position of helicopter body for testing
-    //heli_body_node.rotation = glm::vec3(0.0, 3.0, 0.0); //Set rotation of helicopter body for testing
+        let world = load_world(&options);
+        let mut root_scene_node = world.root;
+        let pilot = options.pilot;
 
-    root_scene_node.add_child(&terrain_scene_node); //Add terrain scene node to the root node
+        //Here I debug. Only in the window, so the headless and golden image runs do not print the whole scene graph
+        root_scene_node.print();
+        if let Some(terrain_scene_node) = root_scene_node.find("terrain") {
+            terrain_scene_node.print();
+        }
+        println!("Scene graph has {} nodes and {} helicopters", root_scene_node.pre_order().count(), root_scene_node.find_by_tag("helicopter").len());
 
-    //Here I debug
-    root_scene_node.print();
-    terrain_scene_node.print();
+        let mut renderer = unsafe { Renderer::new(&world.description.lighting) };
 
         // Used to demonstrate keyboard handling -- feel free to remove
-        let mut _arbitrary_number = 0.0;
+        let _arbitrary_number = 0.0;
 
         //Create needed variables for translation (camera movement)
         let mut _x = 0.0;
@@ -306,45 +655,150 @@ fn main() {
         let mut rot_x = 0.0;
         let mut rot_y = 0.0;
 
-        let first_frame_time = std::time::Instant::now();
-        let mut last_frame_time = first_frame_time;
+        let mut last_frame_time = std::time::Instant::now();
 
-        let identity: glm::Mat4 = glm::identity(); //Create identitiy matrix
-        let projection: glm::Mat4 = glm::perspective(1.00, 1.00, 1.0, 1000.0); //Projection
+        //Simulation time. P pauses, . steps one frame, [ and ] slow down and speed up, Home restarts and
+        //Page Up and Page Down skip five seconds back and forward
+        let mut clock = clock::Clock::new();
+        let mut fixed_timestep = clock::FixedTimestep::new(options.tick_rate, MAX_CATCH_UP_STEPS);
+        clock.step_time = fixed_timestep.step_time; //Stepping while paused moves one simulation step
 
-        let mut angel = 0.0;
+        let identity: glm::Mat4 = glm::identity(); //Create identitiy matrix
+        let projection: glm::Mat4 = glm::perspective(1.00, 1.00, NEAR_PLANE, FAR_PLANE); //Projection
+
+
+        let mut culling_stats = (frustum::CullingStats::default(), 0, 0);
+
+        //Draw nodes that share a VAO with a single instanced draw call. Toggled with I
+        let mut use_instancing = true;
+
+        let mut previous_keys: Vec<VirtualKeyCode> = Vec::new();
+
+        //Follows the helicopter flown with --pilot. While it is on, WASDQE and Space and Left Shift fly the helicopter
+        //instead of moving the camera. Toggled with C
+        let mut chase_camera = camera::ChaseCamera::new(30.0, 10.0, 3.0);
+        let mut use_chase_camera = pilot;
+
+        //F12 saves a screenshot of the next frame. F10 starts and stops recording every frame to a numbered image
+        //sequence in a new directory, with the simulation moving the same amount between frames however long they
+        //take to draw and save, so the recording plays back at the right speed
+        let mut take_screenshot = false;
+        let start_recording = |capture_path: &str| {
+            let directory = format!("{}/recording_{}", capture_path, capture::timestamp());
+            match capture::FrameRecorder::new(&directory, frame_rate) {
+                Ok(recorder) => {
+                    println!("Recording to {}", directory);
+                    Some(recorder)
+                },
+                Err(e) => {
+                    println!("{}", e);
+                    None
+                },
+            }
+        };
+        let mut recorder = if record { start_recording(&capture_path) } else { None };
 
         // The main rendering loop
         loop {
             let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
             let delta_time = now.duration_since(last_frame_time).as_secs_f32();
             last_frame_time = now;
+            let delta_time = recorder.as_ref().map_or(delta_time, |recorder| recorder.frame_time);
             
             let speed = 1.0;
 
             // Handle keyboard input
             if let Ok(keys) = pressed_keys.lock() {
+                //Keys that went down since last frame, used for toggles
+                let just_pressed: Vec<VirtualKeyCode> = keys.iter().filter(|key| !previous_keys.contains(key)).cloned().collect();
+                previous_keys = keys.clone();
+
+                if just_pressed.contains(&VirtualKeyCode::I) {
+                    use_instancing = !use_instancing;
+                }
+                if just_pressed.contains(&VirtualKeyCode::R) {
+                    root_scene_node.send_command(behaviour::Command::ToggleRotors); //Starts or stops the rotors of every helicopter
+                }
+                if just_pressed.contains(&VirtualKeyCode::O) {
+                    root_scene_node.send_command(behaviour::Command::ToggleDoor);
+                }
+                if just_pressed.contains(&VirtualKeyCode::C) && pilot {
+                    use_chase_camera = !use_chase_camera;
+                    chase_camera.reset();
+                }
+                if pilot {
+                    let held = |key: VirtualKeyCode| use_chase_camera && keys.contains(&key);
+                    let axis = |positive, negative| (held(positive) as i32 - held(negative) as i32) as f32;
+                    let input = flight::FlightInput {
+                        collective: axis(VirtualKeyCode::Space, VirtualKeyCode::LShift),
+                        forward: axis(VirtualKeyCode::W, VirtualKeyCode::S),
+                        right: axis(VirtualKeyCode::D, VirtualKeyCode::A),
+                        turn: axis(VirtualKeyCode::E, VirtualKeyCode::Q),
+                    };
+                    if let Some(player) = root_scene_node.find(PLAYER_PATH) {
+                        player.send_command(behaviour::Command::Pilot(input));
+                    }
+                }
+                if just_pressed.contains(&VirtualKeyCode::P) {
+                    clock.toggle_pause();
+                    println!("{} at {:.2}s", if clock.is_paused() { "Paused" } else { "Resumed" }, clock.time());
+                }
+                if just_pressed.contains(&VirtualKeyCode::Period) {
+                    clock.step();
+                }
+                if just_pressed.contains(&VirtualKeyCode::LBracket) || just_pressed.contains(&VirtualKeyCode::RBracket) {
+                    let factor = if just_pressed.contains(&VirtualKeyCode::LBracket) { 0.5 } else { 2.0 };
+                    clock.set_scale(clock.scale() * factor);
+                    println!("Time scale {}", clock.scale());
+                }
+                if just_pressed.contains(&VirtualKeyCode::Home) {
+                    clock.seek(0.0);
+                }
+                if just_pressed.contains(&VirtualKeyCode::PageUp) {
+                    clock.seek(clock.time() - 5.0);
+                }
+                if just_pressed.contains(&VirtualKeyCode::PageDown) {
+                    clock.seek(clock.time() + 5.0);
+                }
+                if just_pressed.contains(&VirtualKeyCode::F5) {
+                    match scene_file::save(&world.description.describe(&root_scene_node, &world.meshes), &saved_scene_path) {
+                        Ok(()) => println!("Saved scene to {}", saved_scene_path),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+                if just_pressed.contains(&VirtualKeyCode::F12) {
+                    take_screenshot = true;
+                }
+                if just_pressed.contains(&VirtualKeyCode::F10) {
+                    recorder = match recorder.take() {
+                        Some(recorder) => {
+                            println!("Recorded {} frames to {}", recorder.frame_count, recorder.directory.display());
+                            None
+                        },
+                        None => start_recording(&capture_path),
+                    };
+                }
+
                 for key in keys.iter() {
                     match key {
 
                          /*Use WASDEQ for camera movements*/
-                        VirtualKeyCode::W => {
+                        VirtualKeyCode::W if !use_chase_camera => {
                             _z += speed;
                         },
-                        VirtualKeyCode::S => {
+                        VirtualKeyCode::S if !use_chase_camera => {
                             _z -= speed;
                         },
-                        VirtualKeyCode::E => {
+                        VirtualKeyCode::E if !use_chase_camera => {
                             _y += speed;
                         },
-                        VirtualKeyCode::Q => {
+                        VirtualKeyCode::Q if !use_chase_camera => {
                             _y -= speed;
                         },
-                        VirtualKeyCode::A => {
+                        VirtualKeyCode::A if !use_chase_camera => {
                             _x += speed;
                         },
-                        VirtualKeyCode::D => {
+                        VirtualKeyCode::D if !use_chase_camera => {
                             _x -= speed;
                         },
 
@@ -376,48 +830,64 @@ fn main() {
             }
 
             unsafe {
-                gl::ClearColor(0.163, 0.163, 0.163, 1.0);
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-
                 // Issue the necessary commands to draw your scene here
 
-                //Here I am using the program ID I get retuned from the shader object
-                gl::UseProgram(shader.program_id);
 
 
                 //let scaling: glm::Mat4 = glm::scaling(&glm::vec3(1.0,1.0,1.0));
 
                 //Translation
                 let translation: glm::Mat4 = glm::translation(&glm::vec3(_x,_y,_z)); //Translate, this gives us the camera movements (upward, downward, sideways (left and right), forward and backward)
-                let transposeTranslation: glm::Mat4 = glm::transpose(&translation); //Transpose the translation matrix
+                let transpose_translation: glm::Mat4 = glm::transpose(&translation); //Transpose the translation matrix
 
                 //Rotation
-                let rotationX: glm::Mat4 = glm::rotation(rot_x, &glm::vec3(1.0,0.0,0.0)); //Rotate about the x-axis 
-                let rotationY: glm::Mat4 = glm::rotation(rot_y, &glm::vec3(0.0,1.0,0.0)); //Rotate about the y-axis
-                let transposeRotationX: glm::Mat4 = glm::transpose(&rotationX); //Transpose rotationX matrix
-                let transposeRotationY: glm::Mat4 = glm::transpose(&rotationY); //Transpose rotationY matrix 
+                let rotation_x: glm::Mat4 = glm::rotation(rot_x, &glm::vec3(1.0,0.0,0.0)); //Rotate about the x-axis 
+                let rotation_y: glm::Mat4 = glm::rotation(rot_y, &glm::vec3(0.0,1.0,0.0)); //Rotate about the y-axis
+                let transpose_rotation_x: glm::Mat4 = glm::transpose(&rotation_x); //Transpose rotationX matrix
+                let transpose_rotation_y: glm::Mat4 = glm::transpose(&rotation_y); //Transpose rotationY matrix 
 
                 //Produce the tranformation matrics from individual transformations                
-                let transformationCombo: glm::Mat4 = transposeRotationX * transposeRotationY * transposeTranslation *  projection * identity; //Multiply to get the transformation matrix which is then passed to the vertex shader to apply the transformation
+                let free_camera_combo: glm::Mat4 = transpose_rotation_x * transpose_rotation_y * transpose_translation *  projection * identity; //Multiply to get the transformation matrix which is then passed to the vertex shader to apply the transformation
+                let free_camera_position = frustum::transform_point(&glm::inverse(&(transpose_rotation_x * transpose_rotation_y * transpose_translation)), &glm::zero()); //Where the view moves the origin from, needed for specular highlights
                 
                 
-                //Here I animate. I update the position and rotation of helicopters. I also animate the rotors
-                let mut offset = 0.0;
-                for x in 0..5 {
-                    let headding = toolbox::simple_heading_animation(elapsed + offset);
-                    heli_bodies[x].rotation = glm::vec3(headding.pitch, headding.yaw, headding.roll);
-                    heli_bodies[x].position = glm::vec3(headding.x, 0.0, headding.z);
-                    offset += 0.8;
-
-                    main_rotors[x].rotation = glm::vec3(0.0, angel, 0.0);
-                    tail_rotors[x].rotation = glm::vec3(angel, 0.0, 0.0);
-
-                    angel += 1.0 * elapsed;
+                //Here I animate and update node transformations
+                let updated = simulate(&mut root_scene_node, &mut fixed_timestep, &clock.tick(delta_time));
+
+                //The chase camera has to know where the helicopter ended up, so it is placed after the update
+                let (transformation_combo, camera_position) = match root_scene_node.find(PLAYER_PATH) {
+                    Some(player) if use_chase_camera => {
+                        let view = glm::transpose(&chase_camera.update(&player.current_transformation_matrix, delta_time));
+                        (view * projection, frustum::transform_point(&glm::inverse(&view), &glm::zero()))
+                    },
+                    _ => (free_camera_combo, free_camera_position),
+                };
+
+                //Here I draw, with nodes that share a VAO drawn with a single instanced draw call if use_instancing is on
+                let (stats, draw_calls) = renderer.draw(&root_scene_node, &transformation_combo, &camera_position, use_instancing);
+
+                //Here I capture the frame, from the back buffer before it is shown
+                if take_screenshot || recorder.is_some() {
+                    let size = context.window().inner_size();
+                    let image = capture::read_pixels(size.width, size.height);
+                    if take_screenshot {
+                        take_screenshot = false;
+                        match capture::save_screenshot(&image, &capture_path) {
+                            Ok(path) => println!("Saved screenshot to {}", path.display()),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                    if let Some(Err(e)) = recorder.as_mut().map(|recorder| recorder.save(&image)) {
+                        println!("{}, stopped recording", e);
+                        recorder = None;
+                    }
                 }
 
-                //Here I update node transformations and draw.
-                update_node_transformations(&mut root_scene_node, &glm::identity());
-                draw_scene(&root_scene_node , &transformationCombo);
+                //Show how many nodes were drawn and culled in the title bar, only touching the window when it changes
+                if (stats, draw_calls, updated) != culling_stats {
+                    culling_stats = (stats, draw_calls, updated);
+                    context.window().set_title(&format!("Gloom-rs | drawn: {} culled: {} draw calls: {} transforms updated: {}", stats.drawn, stats.culled, draw_calls, updated));
+                }
             }
 
             context.swap_buffers().unwrap();
@@ -428,7 +898,7 @@ fn main() {
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
     thread::spawn(move || {
-        if !render_thread.join().is_ok() {
+        if render_thread.join().is_err() {
             if let Ok(mut health) = render_thread_watchdog.write() {
                 println!("Render thread panicked!");
                 *health = false;
@@ -442,7 +912,7 @@ fn main() {
 
         // Terminate program if render thread panics
         if let Ok(health) = render_thread_healthy.read() {
-            if *health == false {
+            if !*health {
                 *control_flow = ControlFlow::Exit;
             }
         }
@@ -472,12 +942,8 @@ fn main() {
                 }
 
                 // Handle escape separately
-                match keycode {
-                    Escape => {
-                        *control_flow = ControlFlow::Exit;
-                    },
-
-                    _ => { }
+                if keycode == Escape {
+                    *control_flow = ControlFlow::Exit;
                 }
             },
             Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
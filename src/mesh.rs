@@ -1,92 +1,70 @@
-use tobj;
+use std::collections::HashMap;
+
+use crate::frustum::BoundingBox;
 
 fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num*4).collect()
 }
 
+// A mesh that has been uploaded to the GPU
+#[derive(Clone, Copy, Debug)]
+pub struct MeshHandle {
+    pub vao_id: u32,
+    pub index_count: i32,
+    pub bounds: Option<BoundingBox>,
+}
+
 pub struct Mesh {
     pub vertices: Vec<f32>,
     pub normals: Vec<f32>,
     pub colors: Vec<f32>,
     pub indices: Vec<u32>,
     pub index_count: i32,
+    pub bounds: Option<BoundingBox>,
 }
 
 impl Mesh {
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
+        let bounds = BoundingBox::from_vertices(&mesh.positions);
         Mesh {
             vertices: mesh.positions,
             normals: mesh.normals,
             indices: mesh.indices,
             colors: generate_color_vec(color, num_verts),
             index_count,
+            bounds,
         }
     }
 }
 
-pub struct Terrain;
-impl Terrain {
-    pub fn load(path: &str) -> Mesh {
-        println!("Loading terrain model...");
-        let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(path, true).expect("Failed to load terrain model");
-        let after = std::time::Instant::now();
-        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
-        if models.len() > 1 || models.len() == 0 { panic!("Please use a model with a single mesh") }
-
-        let terrain = models[0].to_owned();
-        println!("Loaded {} with {} points and {} triangles.", terrain.name, terrain.mesh.positions.len() /3, terrain.mesh.indices.len() / 3);
-
-        Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
-    }
+// Loads meshes out of OBJ files, parsing each file only once even if several of its objects are used
+#[derive(Default)]
+pub struct MeshCache {
+    files: HashMap<String, Vec<tobj::Model>>,
 }
 
-use std::ops::Index;
-pub struct Helicopter {
-    pub body: Mesh,
-    pub main_rotor: Mesh,
-    pub tail_rotor: Mesh,
-    pub door: Mesh,
-}
+impl MeshCache {
+    // Load a single object from an OBJ file. If no object name is given the file has to contain a single mesh
+    pub fn load(&mut self, path: &str, object: Option<&str>, color: [f32; 4]) -> Mesh {
+        let models = self.files.entry(path.to_string()).or_insert_with(|| {
+            println!("Loading {}...", path);
+            let before = std::time::Instant::now();
+            let (models, _materials) = tobj::load_obj(path, true).unwrap_or_else(|e| panic!("Failed to load model {}: {}", path, e));
+            let after = std::time::Instant::now();
+            println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
+            for model in &models {
+                println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+            }
+            models
+        });
 
-// You can use square brackets to access the components of the helicopter, if you want to use loops!
-impl Index<usize> for Helicopter {
-    type Output = Mesh;
-    fn index<'a>(&'a self, i: usize) -> &'a Mesh {
-        match i {
-            0 => &self.body,
-            1 => &self.main_rotor,
-            2 => &self.tail_rotor,
-            3 => &self.door,
-            _ => panic!("Invalid index, try [0,3]"),
-        }
+        let model = match object {
+            Some(name) => models.iter().find(|m| m.name == name).unwrap_or_else(|| panic!("{} has no object named {}", path, name)),
+            None if models.len() == 1 => &models[0],
+            None => panic!("Please use a model with a single mesh, or name the object to use from {}", path),
+        };
+        Mesh::from(model.mesh.clone(), color)
     }
 }
-
-impl Helicopter {
-    pub fn load(path: &str) -> Self {
-        println!("Loading helicopter model...");
-        let before = std::time::Instant::now();
-        let (models, _materials) = tobj::load_obj(path, true).expect("Failed to load helicopter model");
-        let after = std::time::Instant::now();
-        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
-
-        for model in &models {
-            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
-        }
-
-        let body_model = models.iter().find(|m| m.name == "Body_body").expect("Incorrect model file!").to_owned();
-        let main_rotor_model = models.iter().find(|m| m.name == "Main_Rotor_main_rotor").expect("Incorrect model file!").to_owned();
-        let tail_rotor_model = models.iter().find(|m| m.name == "Tail_Rotor_tail_rotor").expect("Incorrect model file!").to_owned();
-        let door_model = models.iter().find(|m| m.name == "Door_door").expect("Incorrect model file!").to_owned();
-
-        Helicopter {
-            body:       Mesh::from(body_model.mesh,         [0.3, 0.3, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh,   [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh,   [0.1, 0.3, 0.1, 1.0]),
-            door:       Mesh::from(door_model.mesh,         [0.1, 0.1, 0.3, 1.0]),
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,267 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{Deserialize, Serialize};
+
+use crate::behaviour::{apply_heading, Behaviour, BehaviourDescription, FrameContext};
+use crate::scene_graph::SceneNode;
+use crate::toolbox::{FlightPath, Heading, SplineKind};
+
+// Missions are helicopter routes written in RON files, see missions/patrol.ron for an example. Every route is a
+// list of waypoints that a helicopter flies through in order, along a Catmull-Rom path so it turns smoothly.
+// Helicopters fly each leg at the speed of the waypoint it starts from, and slow down to the slower of two legs
+// when passing a waypoint. They come to a stop at waypoints they hover at, and at both ends of routes that do not loop.
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Waypoint {
+    pub position: [f32; 2], // x and z, in the space of the helicopter's parent
+    pub altitude: f32, // y, in the same space
+    pub speed: f32, // Units per second on the way to the next waypoint
+    #[serde(default)]
+    pub hover: f32, // Seconds to hover here before flying on
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Route {
+    pub helicopter: String, // Path to the helicopter from the root. It is made from the helicopter prefab if the scene does not have it
+    pub waypoints: Vec<Waypoint>,
+    #[serde(default)]
+    pub looping: bool, // Fly back to the first waypoint and start over after the last one
+    #[serde(default)]
+    pub start_time: f32, // Seconds to wait at the first waypoint before setting off
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mission {
+    pub routes: Vec<Route>,
+}
+
+pub fn load(path: &str) -> Result<Mission, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read mission {}: {}", path, e))?;
+    let mission: Mission = ron::de::from_str(&text).map_err(|e| format!("Failed to parse mission {}: {}", path, e))?;
+    for route in &mission.routes {
+        route.check().map_err(|e| format!("Bad route for {} in mission {}: {}", route.helicopter, path, e))?;
+    }
+    Ok(mission)
+}
+
+// Catch the mistakes that would make a route impossible to fly
+pub fn check_waypoints(waypoints: &[Waypoint], looping: bool) -> Result<(), String> {
+    if waypoints.is_empty() {
+        return Err("it has no waypoints".to_string());
+    }
+    for (i, waypoint) in waypoints.iter().enumerate() {
+        let starts_leg = looping || i + 1 < waypoints.len();
+        if starts_leg && waypoint.speed <= 0.0 {
+            return Err(format!("waypoint {} has speed {}, it has to be above zero", i, waypoint.speed));
+        }
+        if waypoint.hover < 0.0 {
+            return Err(format!("waypoint {} hovers for {} seconds", i, waypoint.hover));
+        }
+    }
+    Ok(())
+}
+
+impl Route {
+    pub fn check(&self) -> Result<(), String> {
+        check_waypoints(&self.waypoints, self.looping)
+    }
+
+    pub fn behaviour(&self) -> BehaviourDescription {
+        BehaviourDescription::Waypoints { waypoints: self.waypoints.clone(), looping: self.looping, start_time: self.start_time }
+    }
+}
+
+// One leg of a route, from a waypoint to the next, including the hover at the waypoint it starts from
+#[derive(Clone, Copy, Debug)]
+struct Leg {
+    start_time: f32, // When the hover at the start of the leg begins, counted from the start of the route
+    hover: f32,
+    duration: f32, // Seconds of flying, after the hover
+    start_distance: f32,
+    length: f32,
+    start_speed: f32,
+    end_speed: f32,
+}
+
+impl Leg {
+    // Distance along the leg and speed, t seconds after setting off. The distance is a cubic Hermite curve that
+    // starts and ends at the given speeds, which keeps going forward since neither is above the average speed
+    fn flight(&self, t: f32) -> (f32, f32) {
+        let s = (t / self.duration).clamp(0.0, 1.0);
+        let (start, end) = (self.start_speed * self.duration, self.end_speed * self.duration);
+        let distance = (s * s * s - 2.0 * s * s + s) * start + (-2.0 * s * s * s + 3.0 * s * s) * self.length + (s * s * s - s * s) * end;
+        let speed = ((3.0 * s * s - 4.0 * s + 1.0) * start + (-6.0 * s * s + 6.0 * s) * self.length + (3.0 * s * s - 2.0 * s) * end) / self.duration;
+        (distance, speed)
+    }
+}
+
+// A route turned into a flight path, with a timetable of when the helicopter is where on it
+pub struct WaypointFlight {
+    waypoints: Vec<Waypoint>,
+    looping: bool,
+    path: FlightPath,
+    legs: Vec<Leg>,
+}
+
+impl WaypointFlight {
+    pub fn new(waypoints: Vec<Waypoint>, looping: bool) -> WaypointFlight {
+        let points = waypoints.iter().map(|w| glm::vec3(w.position[0], w.altitude, w.position[1])).collect();
+        let path = FlightPath::new(points, SplineKind::CatmullRom, looping);
+
+        let n = waypoints.len();
+        let leg_count = if looping && n >= 2 { n } else { n.saturating_sub(1) };
+        let leg_speed = |leg: usize| waypoints[leg % n].speed.max(1e-3);
+        //How fast to pass waypoint i. Zero where the helicopter has to stop
+        let passing_speed = |i: usize| {
+            let stops = waypoints[i % n].hover > 0.0 || (!looping && (i == 0 || i == n - 1));
+            if stops { 0.0 } else { leg_speed(i).min(leg_speed(i + n - 1)) }
+        };
+
+        let mut legs = vec![];
+        let mut time = 0.0;
+        for (i, waypoint) in waypoints.iter().enumerate().take(leg_count) {
+            let start_distance = path.segment_start(i);
+            let end_distance = if i + 1 == leg_count { path.length() } else { path.segment_start(i + 1) };
+            let length = end_distance - start_distance;
+            let leg = Leg {
+                start_time: time,
+                hover: waypoint.hover,
+                duration: (length / leg_speed(i)).max(1e-3),
+                start_distance,
+                length,
+                start_speed: passing_speed(i),
+                end_speed: passing_speed(i + 1),
+            };
+            time += leg.hover + leg.duration;
+            legs.push(leg);
+        }
+        WaypointFlight { waypoints, looping, path, legs }
+    }
+
+    pub fn waypoints(&self) -> &[Waypoint] {
+        &self.waypoints
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.looping
+    }
+
+    // How long it takes to fly the whole route once, not counting the hover at the end of routes that do not loop
+    pub fn duration(&self) -> f32 {
+        self.legs.last().map_or(0.0, |leg| leg.start_time + leg.hover + leg.duration)
+    }
+
+    // Where the helicopter is the given number of seconds after it sets off, level while hovering and banking
+    // into turns while flying
+    pub fn heading(&self, time: f32) -> Heading {
+        let time = if self.looping && self.duration() > 0.0 { time.rem_euclid(self.duration()) } else { time };
+        let leg = self.legs.iter().find(|leg| time < leg.start_time + leg.hover + leg.duration);
+        let (distance, speed) = match leg {
+            None => (self.path.length(), 0.0), //Done with the route, or it has only one waypoint
+            Some(leg) if time < leg.start_time + leg.hover => (leg.start_distance, 0.0),
+            Some(leg) => {
+                let (distance, speed) = leg.flight(time - leg.start_time - leg.hover);
+                (leg.start_distance + distance, speed)
+            },
+        };
+
+        let mut heading = self.path.heading_at(distance, speed);
+        if speed <= 0.0 {
+            heading.pitch = 0.0;
+        }
+        heading
+    }
+}
+
+// Fly a route of waypoints, start_time seconds after the simulation starts
+pub struct WaypointBehaviour {
+    pub flight: WaypointFlight,
+    pub start_time: f32,
+}
+
+impl Behaviour for WaypointBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        apply_heading(node, &self.flight.heading((ctx.time - self.start_time).max(0.0)));
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::Waypoints {
+            waypoints: self.flight.waypoints().to_vec(),
+            looping: self.flight.is_looping(),
+            start_time: self.start_time,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waypoint(x: f32, z: f32, speed: f32, hover: f32) -> Waypoint {
+        Waypoint { position: [x, z], altitude: 10.0, speed, hover }
+    }
+
+    fn position(heading: &Heading) -> glm::Vec3 {
+        glm::vec3(heading.x, heading.y, heading.z)
+    }
+
+    #[test]
+    fn routes_are_checked() {
+        let route = |waypoints: Vec<Waypoint>, looping: bool| Route { helicopter: "heli".to_string(), waypoints, looping, start_time: 0.0 };
+        assert!(route(vec![], false).check().is_err());
+        assert!(route(vec![waypoint(0.0, 0.0, 5.0, 0.0), waypoint(10.0, 0.0, 0.0, 0.0)], false).check().is_ok());
+        assert!(route(vec![waypoint(0.0, 0.0, 5.0, 0.0), waypoint(10.0, 0.0, 0.0, 0.0)], true).check().is_err()); //The last one starts a leg when looping
+        assert!(route(vec![waypoint(0.0, 0.0, -1.0, 0.0), waypoint(10.0, 0.0, 5.0, 0.0)], false).check().is_err());
+        assert!(route(vec![waypoint(0.0, 0.0, 5.0, -2.0)], false).check().is_err());
+    }
+
+    #[test]
+    fn hovers_at_waypoints() {
+        let flight = WaypointFlight::new(vec![waypoint(0.0, 0.0, 5.0, 0.0), waypoint(20.0, 0.0, 5.0, 3.0), waypoint(40.0, 0.0, 5.0, 0.0)], false);
+        let arrival = flight.legs[1].start_time;
+        for &time in &[arrival, arrival + 1.0, arrival + 2.9] {
+            let heading = flight.heading(time);
+            assert!(glm::distance(&position(&heading), &glm::vec3(20.0, 10.0, 0.0)) < 1e-3, "{}", time);
+            assert_eq!(heading.pitch, 0.0);
+        }
+        assert!(flight.heading(arrival + 3.5).x > 20.0);
+        assert!((flight.duration() - (arrival + 3.0 + flight.legs[1].duration)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn stops_at_both_ends() {
+        let flight = WaypointFlight::new(vec![waypoint(0.0, 0.0, 5.0, 0.0), waypoint(20.0, 0.0, 5.0, 0.0), waypoint(20.0, 20.0, 5.0, 0.0)], false);
+        assert!(glm::distance(&position(&flight.heading(0.0)), &glm::vec3(0.0, 10.0, 0.0)) < 1e-3);
+        for &time in &[flight.duration(), flight.duration() + 100.0] {
+            assert!(glm::distance(&position(&flight.heading(time)), &glm::vec3(20.0, 10.0, 20.0)) < 1e-3, "{}", time);
+        }
+        //Setting off and arriving slowly, so it barely moves in the first and last tenth of a second
+        assert!(glm::distance(&position(&flight.heading(0.1)), &glm::vec3(0.0, 10.0, 0.0)) < 0.1);
+        assert!(glm::distance(&position(&flight.heading(flight.duration() - 0.1)), &glm::vec3(20.0, 10.0, 20.0)) < 0.1);
+    }
+
+    #[test]
+    fn looping_routes_start_over() {
+        let square = vec![waypoint(0.0, 0.0, 5.0, 1.0), waypoint(20.0, 0.0, 8.0, 0.0), waypoint(20.0, 20.0, 5.0, 0.0), waypoint(0.0, 20.0, 5.0, 0.0)];
+        let flight = WaypointFlight::new(square, true);
+        assert_eq!(flight.legs.len(), 4);
+        for &time in &[0.5, 3.0, 11.0] {
+            let (a, b) = (flight.heading(time), flight.heading(time + 2.0 * flight.duration()));
+            assert!(glm::distance(&position(&a), &position(&b)) < 1e-2 && (a.yaw - b.yaw).abs() < 1e-3, "{}", time);
+        }
+        assert!(glm::distance(&position(&flight.heading(flight.duration())), &glm::vec3(0.0, 10.0, 0.0)) < 1e-3);
+    }
+
+    #[test]
+    fn legs_move_forward_and_arrive_on_time() {
+        for &(start_speed, end_speed) in &[(0.0, 0.0), (5.0, 0.0), (0.0, 5.0), (5.0, 5.0), (2.0, 4.0)] {
+            let leg = Leg { start_time: 0.0, hover: 0.0, duration: 4.0, start_distance: 0.0, length: 20.0, start_speed, end_speed };
+            assert_eq!(leg.flight(0.0), (0.0, start_speed));
+            let (distance, speed) = leg.flight(leg.duration);
+            assert!((distance - leg.length).abs() < 1e-4 && (speed - end_speed).abs() < 1e-4);
+
+            let distances: Vec<f32> = (0..=100).map(|i| leg.flight(i as f32 / 100.0 * leg.duration).0).collect();
+            assert!(distances.windows(2).all(|pair| pair[1] >= pair[0]), "{} {}", start_speed, end_speed);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::mesh::MeshHandle;
+use crate::scene_graph::{Node, SceneNode};
+use crate::behaviour::BehaviourDescription;
+use crate::lighting::Light;
+
+// One node of a prefab, with the same properties as the SceneNode it becomes
+#[derive(Clone, Debug)]
+pub struct PrefabPart {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub mesh: Option<MeshHandle>,
+    pub position: glm::Vec3,
+    pub orientation: glm::Quat,
+    pub scale: glm::Vec3,
+    pub reference_point: glm::Vec3,
+    pub light: Option<Light>,
+    pub behaviours: Vec<BehaviourDescription>, // Every instance gets its own behaviours made from these
+    pub children: Vec<PrefabPart>,
+}
+
+impl PrefabPart {
+    pub fn new(name: &str) -> PrefabPart {
+        PrefabPart {
+            name: name.to_string(),
+            tags: vec![],
+            mesh: None,
+            position: glm::zero(),
+            orientation: glm::quat_identity(),
+            scale: glm::vec3(1.0, 1.0, 1.0),
+            reference_point: glm::zero(),
+            light: None,
+            behaviours: vec![],
+            children: vec![],
+        }
+    }
+
+    // Create the scene nodes for this part and everything below it. Every node is added to parts, keyed by its
+    // path relative to the root of the instance, which has the empty path.
+    fn build(&self, path: &str, parts: &mut HashMap<String, *mut SceneNode>) -> Node {
+        let mut node = match &self.mesh {
+            Some(mesh) => {
+                let mut node = SceneNode::from_vao(mesh.vao_id, mesh.index_count);
+                node.bounds = mesh.bounds;
+                node
+            },
+            None => SceneNode::new(),
+        };
+        node.name = self.name.clone();
+        node.tags = self.tags.clone();
+        node.position = self.position;
+        node.orientation = self.orientation;
+        node.scale = self.scale;
+        node.reference_point = self.reference_point;
+        node.light = self.light;
+        node.behaviours = self.behaviours.iter().map(BehaviourDescription::create).collect();
+
+        for child in &self.children {
+            let child_path = if path.is_empty() { child.name.clone() } else { format!("{}/{}", path, child.name) };
+            let child_node = child.build(&child_path, parts);
+            node.add_child(&child_node);
+        }
+
+        parts.insert(path.to_string(), &mut **node as *mut SceneNode);
+        node
+    }
+}
+
+// A reusable subtree, like a helicopter with its rotors and door, that can be added to the scene graph any number of times
+#[derive(Clone, Debug)]
+pub struct Prefab {
+    pub root: PrefabPart,
+}
+
+// The nodes created by instantiating a prefab
+pub struct PrefabInstance {
+    parts: HashMap<String, *mut SceneNode>,
+}
+
+impl PrefabInstance {
+    pub fn root(&mut self) -> &mut SceneNode {
+        self.part("").unwrap()
+    }
+
+    // Look up a part by its path relative to the root of the instance, e.g. "main_rotor". Faster than finding it
+    // from the root, since every part was remembered when the instance was made
+    pub fn part(&mut self, path: &str) -> Option<&mut SceneNode> {
+        self.parts.get(path).map(|&node| unsafe { &mut *node })
+    }
+}
+
+impl Prefab {
+    // Create the nodes without attaching them to anything, e.g. to use them as the root of a scene graph
+    pub fn build(&self) -> (Node, PrefabInstance) {
+        let mut parts = HashMap::new();
+        let root = self.root.build("", &mut parts);
+        (root, PrefabInstance { parts })
+    }
+
+    // Create a new copy of the prefab as a child of parent
+    pub fn instantiate(&self, parent: &mut SceneNode) -> PrefabInstance {
+        let (root, instance) = self.build();
+        parent.add_child(&root);
+        instance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A body with a rotor on a mast, and a door
+    fn helicopter() -> Prefab {
+        let mut root = PrefabPart::new("body");
+        let mut mast = PrefabPart::new("mast");
+        mast.position = glm::vec3(0.0, 1.0, 0.0);
+        mast.children.push(PrefabPart::new("rotor"));
+        root.children.push(mast);
+        root.children.push(PrefabPart::new("door"));
+        Prefab { root }
+    }
+
+    #[test]
+    fn parts_are_found_by_path() {
+        let prefab = helicopter();
+        let mut parent = SceneNode::new();
+        let mut instance = prefab.instantiate(&mut parent);
+
+        assert_eq!(instance.root().name, "body");
+        assert_eq!(parent.children, vec![instance.root() as *mut SceneNode]);
+        for path in &["mast", "mast/rotor", "door"] {
+            let found = instance.root().find(path).map(|node| node as *mut SceneNode);
+            assert_eq!(instance.part(path).map(|node| node as *mut SceneNode), found, "{}", path);
+            assert!(found.is_some());
+        }
+        assert!(instance.part("rotor").is_none());
+        assert_eq!(instance.part("mast").unwrap().position, glm::vec3(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn instances_are_independent() {
+        let prefab = helicopter();
+        let mut parent = SceneNode::new();
+        let (mut first, mut second) = (prefab.instantiate(&mut parent), prefab.instantiate(&mut parent));
+        first.part("door").unwrap().position.x = 2.0;
+        assert_eq!(second.part("door").unwrap().position.x, 0.0);
+        assert_ne!(first.part("mast/rotor").unwrap() as *mut SceneNode, second.part("mast/rotor").unwrap() as *mut SceneNode);
+        assert_eq!(parent.children.len(), 2);
+    }
+}
@@ -0,0 +1,243 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{Deserialize, Serialize};
+
+use crate::behaviour::{Behaviour, BehaviourDescription, Command, FrameContext};
+use crate::scene_graph::SceneNode;
+
+const TWO_PI: f32 = 2.0 * std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RotorState {
+    Off,
+    Starting, // Speeding up towards the target RPM
+    #[default]
+    Running,
+    Stopping, // Slowing down to a stop
+}
+
+// The engine and gearbox of a helicopter. The main rotor speeds up and slows down at a limited rate, and the tail
+// rotor is geared to it so it always turns gear_ratio times as fast.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rotor {
+    pub target_rpm: f32,
+    pub spin_up: f32, // How fast the RPM goes up while starting, in RPM per second
+    pub spin_down: f32, // How fast the RPM goes down while stopping, in RPM per second
+    pub gear_ratio: f32, // Tail rotor turns per main rotor turn
+    state: RotorState,
+    rpm: f32,
+    main_angle: f32, // Radians, always in [0, 2pi)
+    tail_angle: f32,
+}
+
+impl Rotor {
+    // A rotor in the given state. Running and stopping rotors start out at the target RPM
+    pub fn new(target_rpm: f32, spin_up: f32, spin_down: f32, gear_ratio: f32, state: RotorState) -> Rotor {
+        let rpm = match state {
+            RotorState::Running | RotorState::Stopping => target_rpm,
+            RotorState::Off | RotorState::Starting => 0.0,
+        };
+        Rotor { target_rpm, spin_up, spin_down, gear_ratio, state, rpm, main_angle: 0.0, tail_angle: 0.0 }
+    }
+
+    pub fn state(&self) -> RotorState {
+        self.state
+    }
+
+    pub fn main_angle(&self) -> f32 {
+        self.main_angle
+    }
+
+    pub fn tail_angle(&self) -> f32 {
+        self.tail_angle
+    }
+
+    // Start speeding up, unless already on the way up
+    pub fn start(&mut self) {
+        if let RotorState::Off | RotorState::Stopping = self.state {
+            self.state = RotorState::Starting;
+        }
+    }
+
+    // Start slowing down, unless already on the way down
+    pub fn stop(&mut self) {
+        if let RotorState::Starting | RotorState::Running = self.state {
+            self.state = RotorState::Stopping;
+        }
+    }
+
+    // Finish speeding up or slowing down right away
+    pub fn settle(&mut self) {
+        match self.state {
+            RotorState::Starting => self.state = RotorState::Running,
+            RotorState::Stopping => self.state = RotorState::Off,
+            RotorState::Running | RotorState::Off => {},
+        }
+        self.update(0.0);
+    }
+
+    pub fn toggle(&mut self) {
+        match self.state {
+            RotorState::Off | RotorState::Stopping => self.start(),
+            RotorState::Starting | RotorState::Running => self.stop(),
+        }
+    }
+
+    // Advance the rotor delta_time seconds
+    pub fn update(&mut self, delta_time: f32) {
+        let previous_rpm = self.rpm;
+        match self.state {
+            RotorState::Starting => {
+                self.rpm += self.spin_up * delta_time;
+                if self.rpm >= self.target_rpm {
+                    self.rpm = self.target_rpm;
+                    self.state = RotorState::Running;
+                }
+            },
+            RotorState::Stopping => {
+                self.rpm -= self.spin_down * delta_time;
+                if self.rpm <= 0.0 {
+                    self.rpm = 0.0;
+                    self.state = RotorState::Off;
+                }
+            },
+            RotorState::Running => self.rpm = self.target_rpm,
+            RotorState::Off => self.rpm = 0.0,
+        }
+
+        //The average speed over the step, so the angle does not depend on the frame rate while speeding up
+        let turned = (previous_rpm + self.rpm) / 2.0 / 60.0 * TWO_PI * delta_time;
+        self.main_angle = (self.main_angle + turned).rem_euclid(TWO_PI);
+        self.tail_angle = (self.tail_angle + turned * self.gear_ratio).rem_euclid(TWO_PI);
+    }
+}
+
+// Turns the main and tail rotor nodes of a helicopter, found by their paths relative to the node this is attached to
+pub struct RotorBehaviour {
+    pub rotor: Rotor,
+    pub main_rotor: String,
+    pub main_axis: glm::Vec3,
+    pub tail_rotor: String,
+    pub tail_axis: glm::Vec3,
+}
+
+impl Behaviour for RotorBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        //There is no telling how far the rotor got across a jump in time, so it is as if it was long ago
+        if ctx.seeked {
+            self.rotor.settle();
+        }
+        self.rotor.update(ctx.delta_time);
+        if let Some(main_rotor) = node.find(&self.main_rotor) {
+            main_rotor.set_axis_angle(&self.main_axis, self.rotor.main_angle());
+        }
+        if let Some(tail_rotor) = node.find(&self.tail_rotor) {
+            tail_rotor.set_axis_angle(&self.tail_axis, self.rotor.tail_angle());
+        }
+    }
+
+    fn command(&mut self, command: Command) {
+        match command {
+            Command::StartRotors => self.rotor.start(),
+            Command::StopRotors => self.rotor.stop(),
+            Command::ToggleRotors => self.rotor.toggle(),
+            _ => {},
+        }
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::Rotors {
+            main_rotor: self.main_rotor.clone(),
+            main_axis: self.main_axis.into(),
+            tail_rotor: self.tail_rotor.clone(),
+            tail_axis: self.tail_axis.into(),
+            target_rpm: self.rotor.target_rpm,
+            spin_up: self.rotor.spin_up,
+            spin_down: self.rotor.spin_down,
+            gear_ratio: self.rotor.gear_ratio,
+            state: self.rotor.state(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    // Up to 300 RPM in 3 seconds, down in 6, with the tail rotor turning 5 times as fast
+    fn heli_rotor(state: RotorState) -> Rotor {
+        Rotor::new(300.0, 100.0, 50.0, 5.0, state)
+    }
+
+    #[test]
+    fn starts_and_stops() {
+        let mut rotor = heli_rotor(RotorState::Off);
+        rotor.update(1.0);
+        assert_eq!((rotor.state(), rotor.rpm, rotor.main_angle()), (RotorState::Off, 0.0, 0.0));
+
+        rotor.start();
+        assert_eq!(rotor.state(), RotorState::Starting);
+        rotor.update(1.0);
+        assert_eq!((rotor.state(), rotor.rpm), (RotorState::Starting, 100.0));
+        rotor.update(1.5);
+        assert_eq!((rotor.state(), rotor.rpm), (RotorState::Starting, 250.0));
+        rotor.update(1.5); //Reaches the target half a second in
+        assert_eq!((rotor.state(), rotor.rpm), (RotorState::Running, 300.0));
+        rotor.update(0.1);
+        assert_eq!((rotor.state(), rotor.rpm), (RotorState::Running, 300.0));
+
+        rotor.stop();
+        assert_eq!(rotor.state(), RotorState::Stopping);
+        rotor.update(2.0);
+        assert_eq!((rotor.state(), rotor.rpm), (RotorState::Stopping, 200.0));
+        rotor.update(10.0);
+        assert_eq!((rotor.state(), rotor.rpm), (RotorState::Off, 0.0));
+    }
+
+    #[test]
+    fn toggling_turns_around_half_way() {
+        let mut rotor = heli_rotor(RotorState::Running);
+        assert_eq!(rotor.rpm, 300.0);
+        rotor.toggle();
+        rotor.update(1.0);
+        assert_eq!((rotor.state(), rotor.rpm), (RotorState::Stopping, 250.0));
+        rotor.toggle();
+        assert_eq!(rotor.state(), RotorState::Starting);
+        rotor.update(0.5);
+        assert_eq!((rotor.state(), rotor.rpm), (RotorState::Running, 300.0));
+
+        //Starting an already running rotor, or stopping one that is already off, changes nothing
+        rotor.start();
+        assert_eq!(rotor.state(), RotorState::Running);
+        let mut off = heli_rotor(RotorState::Off);
+        off.stop();
+        assert_eq!(off.state(), RotorState::Off);
+    }
+
+    #[test]
+    fn angles_follow_the_rpm() {
+        //Speeding up from 0 to 100 RPM in a second averages 50 RPM, which is 5/6 of a turn in that second
+        let mut rotor = heli_rotor(RotorState::Starting);
+        rotor.update(1.0);
+        assert!(approx(rotor.main_angle(), 5.0 / 6.0 * TWO_PI));
+        assert!(approx(rotor.tail_angle(), (5.0 * 5.0 / 6.0 * TWO_PI).rem_euclid(TWO_PI)));
+
+        //The same speed up in many small steps turns it just as far
+        let mut stepped = heli_rotor(RotorState::Starting);
+        for _ in 0..100 {
+            stepped.update(0.01);
+        }
+        assert!(approx(stepped.rpm, 100.0) && approx(stepped.main_angle(), rotor.main_angle()));
+
+        //Angles stay wrapped
+        let mut running = heli_rotor(RotorState::Running);
+        for _ in 0..1000 {
+            running.update(0.37);
+            assert!((0.0..TWO_PI).contains(&running.main_angle()) && (0.0..TWO_PI).contains(&running.tail_angle()));
+        }
+    }
+}
@@ -0,0 +1,294 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mesh::MeshHandle;
+use crate::prefab::{Prefab, PrefabPart};
+use crate::scene_graph::{quat_from_euler, quat_look_along, EulerOrder, Node, SceneNode};
+use crate::behaviour::BehaviourDescription;
+use crate::lighting::{Light, Lighting};
+
+// Scenes are described in RON files, see scenes/lunar.ron for an example. Everything except the root node
+// can be left out, and gets the same defaults as SceneNode::new.
+//
+// A node that refers to a prefab gets the prefab's mesh, reference point and children. Its own name and transform
+// replace the prefab's, and its own tags, behaviours and children are added to the prefab's.
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneDescription {
+    #[serde(default)]
+    pub materials: BTreeMap<String, Material>,
+    #[serde(default)]
+    pub meshes: BTreeMap<String, MeshSource>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prefabs: BTreeMap<String, NodeDescription>,
+    #[serde(default)]
+    pub lighting: Lighting, // A single white sun if left out
+    pub root: NodeDescription,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Material {
+    pub color: [f32; 4], // Used as the vertex color of every mesh with this material
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MeshSource {
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub object: Option<String>, // Name of the object to use, needed when the OBJ file contains more than one
+    pub material: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Rotation {
+    Euler { angles: [f32; 3], order: EulerOrder },
+    Quaternion([f32; 4]), // x, y, z, w
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation::Quaternion([0.0, 0.0, 0.0, 1.0])
+    }
+}
+
+impl Rotation {
+    pub fn to_quat(self) -> glm::Quat {
+        match self {
+            Rotation::Euler { angles, order } => quat_from_euler(&glm::make_vec3(&angles), order),
+            Rotation::Quaternion([x, y, z, w]) => glm::quat(x, y, z, w),
+        }
+    }
+
+    pub fn from_quat(q: &glm::Quat) -> Rotation {
+        Rotation::Quaternion([q.coords.x, q.coords.y, q.coords.z, q.coords.w])
+    }
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeDescription {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefab: Option<String>, // Key into SceneDescription::prefabs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<String>, // Key into SceneDescription::meshes, nodes without a mesh are not drawn
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub rotation: Rotation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub look_at: Option<[f32; 3]>, // Turn the node to face this point in the parent's space, instead of using rotation
+    #[serde(default = "unit_scale")]
+    pub scale: [f32; 3],
+    #[serde(default)]
+    pub reference_point: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub light: Option<Light>, // Attached to the node, positioned and aimed in its model space
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub behaviours: Vec<BehaviourDescription>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<NodeDescription>,
+}
+
+pub struct LoadedScene {
+    pub root: Node,
+    pub meshes: HashMap<String, MeshHandle>, // The meshes the scene uses, by their key in SceneDescription::meshes
+    pub prefabs: HashMap<String, Prefab>, // Every prefab in the scene file, so more copies can be added later
+}
+
+pub fn load(path: &str) -> Result<SceneDescription, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Failed to read scene {}: {}", path, e))?;
+    ron::de::from_str(&text).map_err(|e| format!("Failed to parse scene {}: {}", path, e))
+}
+
+pub fn save(description: &SceneDescription, path: &str) -> Result<(), String> {
+    let text = ron::ser::to_string_pretty(description, ron::ser::PrettyConfig::default())
+        .map_err(|e| format!("Failed to serialize scene: {}", e))?;
+    std::fs::write(path, text).map_err(|e| format!("Failed to write scene {}: {}", path, e))
+}
+
+impl SceneDescription {
+    // Build the scene graph. upload_mesh is called once for every mesh the nodes use, and should load it and set up its VAO
+    pub fn build<F>(&self, mut upload_mesh: F) -> Result<LoadedScene, String>
+        where F: FnMut(&MeshSource, &Material) -> MeshHandle
+    {
+        let mut meshes = HashMap::new();
+        let mut mesh = |key: &str| {
+            if let Some(handle) = meshes.get(key) {
+                return Ok(*handle);
+            }
+            let source = self.meshes.get(key).ok_or_else(|| format!("Unknown mesh {}", key))?;
+            let material = self.materials.get(&source.material).ok_or_else(|| format!("Unknown material {}", source.material))?;
+            let handle = upload_mesh(source, material);
+            meshes.insert(key.to_string(), handle);
+            Ok(handle)
+        };
+
+        let mut prefabs = HashMap::new();
+        for (name, description) in &self.prefabs {
+            let root = self.to_part(description, &mut mesh, &mut vec![name])?;
+            prefabs.insert(name.clone(), Prefab { root });
+        }
+        let (root, _) = Prefab { root: self.to_part(&self.root, &mut mesh, &mut vec![])? }.build();
+
+        Ok(LoadedScene { root, meshes, prefabs })
+    }
+
+    // Resolve the meshes and prefabs a node refers to. prefab_stack holds the prefabs being resolved, to catch prefabs that contain themselves
+    fn to_part<'a>(&'a self, description: &'a NodeDescription, mesh: &mut dyn FnMut(&str) -> Result<MeshHandle, String>, prefab_stack: &mut Vec<&'a String>) -> Result<PrefabPart, String> {
+        let mut part = match &description.prefab {
+            Some(name) => {
+                if prefab_stack.contains(&name) {
+                    return Err(format!("Prefab {} contains itself", name));
+                }
+                let prefab = self.prefabs.get(name).ok_or_else(|| format!("Unknown prefab {}", name))?;
+                prefab_stack.push(name);
+                let part = self.to_part(prefab, mesh, prefab_stack)?;
+                prefab_stack.pop();
+                part
+            },
+            None => PrefabPart::new(&description.name),
+        };
+        if let Some(key) = &description.mesh {
+            part.mesh = Some(mesh(key)?);
+        }
+        if description.prefab.is_none() {
+            part.reference_point = glm::make_vec3(&description.reference_point);
+        }
+        part.name = description.name.clone();
+        part.tags.extend(description.tags.iter().cloned());
+        part.position = glm::make_vec3(&description.position);
+        part.orientation = match description.look_at {
+            Some(target) => quat_look_along(&(glm::make_vec3(&target) - part.position), &glm::vec3(0.0, 1.0, 0.0)),
+            None => description.rotation.to_quat(),
+        };
+        part.scale = glm::make_vec3(&description.scale);
+        if description.light.is_some() {
+            part.light = description.light;
+        }
+        for behaviour in &description.behaviours {
+            behaviour.check().map_err(|e| format!("Bad behaviour on {}: {}", description.name, e))?;
+        }
+        part.behaviours.extend(description.behaviours.iter().cloned());
+
+        for child in &description.children {
+            part.children.push(self.to_part(child, mesh, prefab_stack)?);
+        }
+        Ok(part)
+    }
+
+    // Describe the current state of a scene graph that was built from this description, so it can be saved.
+    // Materials, mesh sources and prefabs are kept from this description, but nodes that were made from prefabs are
+    // written out in full.
+    pub fn describe(&self, root: &SceneNode, meshes: &HashMap<String, MeshHandle>) -> SceneDescription {
+        let mesh_keys: HashMap<u32, &String> = meshes.iter().map(|(key, handle)| (handle.vao_id, key)).collect();
+
+        //Post-order visits the children of a node right before it, so their descriptions are the last ones made
+        let mut described: Vec<NodeDescription> = vec![];
+        for node in root.post_order() {
+            let children = described.split_off(described.len() - node.children.len());
+            described.push(describe_node(node, children, &mesh_keys));
+        }
+        SceneDescription {
+            materials: self.materials.clone(),
+            meshes: self.meshes.clone(),
+            prefabs: self.prefabs.clone(),
+            lighting: self.lighting.clone(),
+            root: described.pop().unwrap(),
+        }
+    }
+}
+
+fn describe_node(node: &SceneNode, children: Vec<NodeDescription>, mesh_keys: &HashMap<u32, &String>) -> NodeDescription {
+    NodeDescription {
+        name: node.name.clone(),
+        tags: node.tags.clone(),
+        prefab: None,
+        mesh: if node.index_count > -1 { mesh_keys.get(&node.vao_id).map(|key| key.to_string()) } else { None },
+        position: node.position.into(),
+        rotation: Rotation::from_quat(&node.orientation),
+        look_at: None,
+        scale: node.scale.into(),
+        reference_point: node.reference_point.into(),
+        light: node.light,
+        behaviours: node.behaviours.iter().filter_map(|behaviour| {
+            let description = behaviour.describe();
+            if description.is_none() {
+                println!("{} on {} can't be written to a scene file, so it is left out", behaviour.name(), node.name);
+            }
+            description
+        }).collect(),
+        children,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds the scene without OpenGL, every mesh gets a made up VAO
+    fn build(description: &SceneDescription) -> LoadedScene {
+        let mut vao_id = 0;
+        description.build(|_, _| {
+            vao_id += 1;
+            MeshHandle { vao_id, index_count: 3, bounds: None }
+        }).unwrap()
+    }
+
+    #[test]
+    fn saved_scene_loads_the_same() {
+        let original = load("scenes/lunar.ron").unwrap();
+        let scene = build(&original);
+        let described = original.describe(&scene.root, &scene.meshes);
+
+        let path = std::env::temp_dir().join(format!("gloom-rs-round-trip-{}.ron", std::process::id()));
+        save(&described, path.to_str().unwrap()).unwrap();
+        let reloaded = load(path.to_str().unwrap());
+        std::fs::remove_file(&path).ok();
+        let reloaded = reloaded.unwrap();
+        assert_eq!(reloaded, described);
+
+        //Saving writes prefab instances out in full, so compare what the scenes build into rather than the files
+        let rebuilt = build(&reloaded);
+        assert_eq!(reloaded.describe(&rebuilt.root, &rebuilt.meshes), described);
+        let summary = |root: &SceneNode| root.pre_order()
+            .map(|node| (node.name.clone(), node.position, node.orientation, node.scale, node.index_count, node.behaviours.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary(&rebuilt.root), summary(&scene.root));
+    }
+
+    #[test]
+    fn routes_that_cant_be_flown_are_rejected() {
+        let scene = |waypoints: &str| ron::de::from_str::<SceneDescription>(&format!("(root: (behaviours: [Waypoints(waypoints: [{}])]))", waypoints)).unwrap();
+        assert!(scene("").build(|_, _| unreachable!()).is_err());
+        assert!(scene("(position: (0.0, 0.0), altitude: 5.0, speed: 0.0), (position: (10.0, 0.0), altitude: 5.0, speed: 4.0)").build(|_, _| unreachable!()).is_err());
+        assert!(scene("(position: (0.0, 0.0), altitude: 5.0, speed: 4.0), (position: (10.0, 0.0), altitude: 5.0, speed: 0.0)").build(|_, _| unreachable!()).is_ok());
+    }
+
+    #[test]
+    fn behaviours_that_cant_be_saved_are_left_out() {
+        use crate::behaviour::Behaviour;
+        use crate::flight::PilotBehaviour;
+
+        let original = load("scenes/lunar.ron").unwrap();
+        let mut scene = build(&original);
+        let pilot = PilotBehaviour::new(None);
+        assert_eq!(pilot.name(), "PilotBehaviour");
+        let terrain = scene.root.find("terrain").unwrap();
+        let behaviour_count = terrain.behaviours.len();
+        terrain.behaviours.push(Box::new(pilot));
+
+        let described = original.describe(&scene.root, &scene.meshes);
+        let terrain = described.root.children.iter().find(|node| node.name == "terrain").unwrap();
+        assert_eq!(terrain.behaviours.len(), behaviour_count);
+    }
+}
@@ -1,8 +1,15 @@
 extern crate nalgebra_glm as glm;
 
+use crate::frustum::BoundingBox;
+use crate::behaviour::{Behaviour, Command, FrameContext};
+use crate::lighting::Light;
+
+use std::collections::VecDeque;
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
 
+use serde::{Deserialize, Serialize};
+
 // Used to crete an unholy abomination upon which you should not cast your gaze.
 // This ended up being a necessity due to wanting to keep the code written by students as "straight forward" as possible
 // It is very very double plus ungood Rust, and intentionally leaks memory like a sieve. But it works, and you're more than welcome to pretend it doesn't exist!
@@ -10,49 +17,310 @@ use std::pin::Pin;
 // and finally prevents the compiler from dropping it automatically at all (ManuallyDrop). If that sounds like a janky solution, it's because it is.
 // Prettier, Rustier and better solutions were tried numerous times, but were all found wanting of having what I arbitrarily decided to be the required level of
 // simplicity of use.
-type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
+
+// The order in which Euler angle rotations are composed. Xyz means the rotation matrix is Rx * Ry * Rz,
+// so the Z rotation is applied to the model first and the X rotation last.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EulerOrder {
+    Xyz,
+    Xzy,
+    Yxz,
+    Yzx,
+    Zxy,
+    Zyx,
+}
+
+// Quaternion equivalent of composing the rotations about each axis in the given order
+pub fn quat_from_euler(angles: &glm::Vec3, order: EulerOrder) -> glm::Quat {
+    let x = glm::quat_angle_axis(angles.x, &glm::vec3(1.0, 0.0, 0.0));
+    let y = glm::quat_angle_axis(angles.y, &glm::vec3(0.0, 1.0, 0.0));
+    let z = glm::quat_angle_axis(angles.z, &glm::vec3(0.0, 0.0, 1.0));
+    match order {
+        EulerOrder::Xyz => x * y * z,
+        EulerOrder::Xzy => x * z * y,
+        EulerOrder::Yxz => y * x * z,
+        EulerOrder::Yzx => y * z * x,
+        EulerOrder::Zxy => z * x * y,
+        EulerOrder::Zyx => z * y * x,
+    }
+}
+
+// Rotation that turns the -z axis, which is forward for our models, to point along direction. When direction is
+// parallel to up any roll is as good as another, so one is picked using another axis as up
+pub fn quat_look_along(direction: &glm::Vec3, up: &glm::Vec3) -> glm::Quat {
+    if glm::length(direction) < 1e-6 {
+        return glm::quat_identity();
+    }
+    let forward = glm::normalize(direction);
+    let mut right = glm::cross(&forward, up);
+    if glm::length(&right) < 1e-6 {
+        let other_up = if forward.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 0.0, 1.0) };
+        right = glm::cross(&forward, &other_up);
+    }
+    let right = glm::normalize(&right);
+    let true_up = glm::cross(&right, &forward);
+    let basis = glm::mat3(
+        right.x, true_up.x, -forward.x,
+        right.y, true_up.y, -forward.y,
+        right.z, true_up.z, -forward.z,
+    );
+    glm::quat_normalize(&glm::mat3_to_quat(&basis))
+}
+
+// The values a local transformation matrix is built from
+#[derive(Clone, Copy, PartialEq)]
+struct LocalTransform {
+    position: glm::Vec3,
+    orientation: glm::Quat,
+    scale: glm::Vec3,
+    reference_point: glm::Vec3,
+}
+
+impl LocalTransform {
+    fn of(node: &SceneNode) -> LocalTransform {
+        LocalTransform {
+            position: node.position,
+            orientation: node.orientation,
+            scale: node.scale,
+            reference_point: node.reference_point,
+        }
+    }
+
+    // Part of the way from previous to this, t = 0 giving previous
+    fn interpolated_from(&self, previous: &LocalTransform, t: f32) -> LocalTransform {
+        LocalTransform {
+            position: glm::lerp(&previous.position, &self.position, t),
+            orientation: glm::quat_slerp(&previous.orientation, &self.orientation, t),
+            scale: glm::lerp(&previous.scale, &self.scale, t),
+            reference_point: glm::lerp(&previous.reference_point, &self.reference_point, t),
+        }
+    }
+
+    // Rotates about the reference point, then moves to the position. In the row vector convention used by the
+    // shaders, so this is the transpose of the usual glm matrix. See frustum::transform_point
+    fn matrix(&self) -> glm::Mat4 {
+        let to_reference_point = glm::translation(&self.reference_point);
+        let from_reference_point = glm::translation(&-self.reference_point);
+        let rotation = glm::quat_to_mat4(&self.orientation);
+        let scaling = glm::scaling(&self.scale);
+        glm::transpose(&(glm::translation(&self.position) * to_reference_point * rotation * scaling * from_reference_point))
+    }
+}
 
 pub struct SceneNode {
+    pub name: String, // Used to look the node up with find, should be unique among its siblings
+    pub tags: Vec<String>,
+
     pub position: glm::Vec3,
-    pub rotation: glm::Vec3,
+    pub orientation: glm::Quat,
     pub scale: glm::Vec3,
     pub reference_point: glm::Vec3,
 
-    pub current_transformation_matrix: glm::Mat4,
+    pub local_transformation_matrix: glm::Mat4, // Relative to the parent, updated by update_transformations
+    pub current_transformation_matrix: glm::Mat4, // Relative to the world, updated by update_transformations
+    local_transform: Option<LocalTransform>,
+    previous_transform: Option<LocalTransform>, // Saved by store_previous_transforms, for interpolating between updates
 
     pub vao_id: u32,
     pub index_count: i32,
 
+    pub bounds: Option<BoundingBox>, // Bounds of the node's own mesh, in model space
+    pub world_bounds: Option<BoundingBox>, // Bounds of the node and all its descendants, in world space
+
+    pub light: Option<Light>, // Shines from the node, in its model space, so it moves and turns with it
+
+    pub behaviours: Vec<Box<dyn Behaviour>>,
+    commands: Vec<Command>, // Sent to this node, waiting to be passed to its behaviours
+
     pub children: Vec<*mut SceneNode>,
 }
 
 impl SceneNode {
     pub fn new() -> Node {
         ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+            name: String::new(),
+            tags: vec![],
             position: glm::zero(),
-            rotation: glm::zero(),
+            orientation: glm::quat_identity(),
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
+            local_transformation_matrix: glm::identity(),
             current_transformation_matrix: glm::identity(),
+            local_transform: None,
+            previous_transform: None,
             vao_id: 0,
             index_count: -1,
+            bounds: None,
+            world_bounds: None,
+            light: None,
+            behaviours: vec![],
+            commands: vec![],
             children: vec![],
         })))
     }
     pub fn from_vao(vao_id: u32, index_count: i32) -> Node {
         ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+            name: String::new(),
+            tags: vec![],
             position: glm::zero(),
-            rotation: glm::zero(),
+            orientation: glm::quat_identity(),
             scale: glm::vec3(1.0, 1.0, 1.0),
             reference_point: glm::zero(),
+            local_transformation_matrix: glm::identity(),
             current_transformation_matrix: glm::identity(),
+            local_transform: None,
+            previous_transform: None,
             vao_id, index_count,
+            bounds: None,
+            world_bounds: None,
+            light: None,
+            behaviours: vec![],
+            commands: vec![],
             children: vec![],
         })))
     }
+    pub fn set_euler(&mut self, angles: &glm::Vec3, order: EulerOrder) {
+        self.orientation = quat_from_euler(angles, order);
+    }
+    pub fn set_axis_angle(&mut self, axis: &glm::Vec3, angle: f32) {
+        self.orientation = if glm::length(axis) > 0.0 { glm::quat_angle_axis(angle, axis) } else { glm::quat_identity() };
+    }
+    // Spherical interpolation from the current orientation towards target, t = 0 keeps the current orientation
+    pub fn slerp_towards(&mut self, target: &glm::Quat, t: f32) {
+        self.orientation = glm::quat_slerp(&self.orientation, target, t);
+    }
+    // Remember the current transforms of this subtree, so update_transformations can blend from them to whatever
+    // they are changed to next
+    pub fn store_previous_transforms(&mut self) {
+        self.previous_transform = Some(LocalTransform::of(self));
+        for &child in &self.children {
+            unsafe { (*child).store_previous_transforms() };
+        }
+    }
+    // Rebuild the local matrix if the transform it should be built from changed since it was last built. That is
+    // the transform stored by store_previous_transforms blended a fraction alpha of the way to the current one.
+    // Returns whether it changed.
+    fn update_local_transformation(&mut self, alpha: f32) -> bool {
+        let current = LocalTransform::of(self);
+        let local_transform = match &self.previous_transform {
+            Some(previous) if alpha < 1.0 && *previous != current => current.interpolated_from(previous, alpha),
+            _ => current,
+        };
+        if self.local_transform == Some(local_transform) {
+            return false;
+        }
+        self.local_transform = Some(local_transform);
+        self.local_transformation_matrix = local_transform.matrix();
+        true
+    }
+    // Update the world matrices of this subtree, only recomputing the ones whose local transform or any ancestor's
+    // changed. Also keeps world_bounds up to date. Returns the number of world matrices that were recomputed.
+    // With alpha below 1 the nodes are drawn part of the way between their previous and current transforms, see
+    // store_previous_transforms.
+    pub fn update_transformations(&mut self, transformation_so_far: &glm::Mat4, parent_changed: bool, alpha: f32) -> u32 {
+        let changed = self.update_local_transformation(alpha) || parent_changed;
+        let mut recomputed = 0;
+        if changed {
+            self.current_transformation_matrix = self.local_transformation_matrix * transformation_so_far;
+            recomputed += 1;
+        }
+
+        let mut subtree_changed = changed;
+        for &child in &self.children {
+            let child_recomputed = unsafe { (*child).update_transformations(&self.current_transformation_matrix, changed, alpha) };
+            subtree_changed |= child_recomputed > 0;
+            recomputed += child_recomputed;
+        }
+
+        //Bounds of this subtree in world space, used for frustum culling
+        if subtree_changed {
+            let own_bounds = self.bounds.map(|b| b.transformed(&self.current_transformation_matrix));
+            self.world_bounds = self.children.iter()
+                .filter_map(|&child| unsafe { (*child).world_bounds })
+                .fold(own_bounds, |bounds, child_bounds| Some(match bounds {
+                    Some(b) => b.union(&child_bounds),
+                    None => child_bounds,
+                }));
+        }
+        recomputed
+    }
     pub fn add_child(&mut self, child: &SceneNode) {
         self.children.push(child as *const SceneNode as *mut SceneNode)
     }
+    // Run the behaviours of every node in this subtree, parents before their children
+    pub fn update_behaviours(&mut self, ctx: &FrameContext) {
+        self.deliver_commands();
+        let mut behaviours = std::mem::take(&mut self.behaviours); //Taken out while running, so they can get the node mutably
+        for behaviour in behaviours.iter_mut() {
+            behaviour.update(self, ctx);
+        }
+        behaviours.append(&mut self.behaviours); //In case a behaviour added more to the node
+        self.behaviours = behaviours;
+        self.deliver_commands(); //Commands the behaviours just sent to their own node
+        for &child in &self.children {
+            unsafe { (*child).update_behaviours(ctx) };
+        }
+    }
+    // Send a command to the behaviours of this node and every node below it. They get it the next time
+    // update_behaviours runs, which means behaviours can send commands to their own node while they are updated.
+    pub fn send_command(&mut self, command: Command) {
+        self.commands.push(command);
+        for &child in &self.children {
+            unsafe { (*child).send_command(command) };
+        }
+    }
+    fn deliver_commands(&mut self) {
+        for command in std::mem::take(&mut self.commands) {
+            for behaviour in self.behaviours.iter_mut() {
+                behaviour.command(command);
+            }
+        }
+    }
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+    // Look up a descendant by the names along the path to it, e.g. "terrain/heli_3/main_rotor".
+    // The path is relative to this node, so it starts with the name of one of its children.
+    pub fn find(&mut self, path: &str) -> Option<&mut SceneNode> {
+        let mut node: *mut SceneNode = self;
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            node = unsafe { *(*node).children.iter().find(|&&child| (*child).name == name)? };
+        }
+        Some(unsafe { &mut *node })
+    }
+    // Every node in this subtree, including this node, with the given tag, in pre-order
+    pub fn find_by_tag(&mut self, tag: &str) -> Vec<&mut SceneNode> {
+        let mut found = vec![];
+        let mut stack: Vec<*mut SceneNode> = vec![self];
+        while let Some(node) = stack.pop() {
+            let node = unsafe { &mut *node };
+            stack.extend(node.children.iter().rev());
+            if node.has_tag(tag) {
+                found.push(node);
+            }
+        }
+        found
+    }
+    // Iterators over this subtree, including this node
+    pub fn pre_order(&self) -> PreOrder<'_> {
+        PreOrder { stack: vec![self] }
+    }
+    pub fn post_order(&self) -> PostOrder<'_> {
+        PostOrder { stack: vec![(self, false)] }
+    }
+    pub fn breadth_first(&self) -> BreadthFirst<'_> {
+        BreadthFirst { queue: vec![self].into() }
+    }
+    // Number of drawable nodes in this subtree, including this node
+    pub fn drawable_count(&self) -> u32 {
+        self.breadth_first().filter(|node| node.index_count > -1).count() as u32
+    }
+    // Add the lights of this subtree to lights, in world space. Uses the world matrices from the last
+    // update_transformations
+    pub fn collect_lights(&self, lights: &mut Vec<Light>) {
+        lights.extend(self.pre_order().filter_map(|node| node.light.map(|light| light.transformed(&node.current_transformation_matrix))));
+    }
     pub fn print(&self) {
         let m = self.current_transformation_matrix;
         let matrix_string = format!(
@@ -69,23 +337,28 @@ impl SceneNode {
         );
         println!(
 "SceneNode {{
+    Name:      {}
+    Tags:      {:?}
     VAO:       {}
     Indices:   {}
     Children:  {}
     Position:  [{:.2}, {:.2}, {:.2}]
-    Rotation:  [{:.2}, {:.2}, {:.2}]
+    Rotation:  [{:.2}, {:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
     Current Transformation Matrix: {}
 }}",
+            self.name,
+            self.tags,
             self.vao_id,
             self.index_count,
             self.children.len(),
             self.position.x,
             self.position.y,
             self.position.z,
-            self.rotation.x,
-            self.rotation.y,
-            self.rotation.z,
+            self.orientation.coords.x,
+            self.orientation.coords.y,
+            self.orientation.coords.z,
+            self.orientation.coords.w,
             self.reference_point.x,
             self.reference_point.y,
             self.reference_point.z,
@@ -93,3 +366,200 @@ impl SceneNode {
         );
     }
 }
+
+
+// Children are visited in the order they were added
+pub struct PreOrder<'a> {
+    stack: Vec<&'a SceneNode>,
+}
+
+impl<'a> Iterator for PreOrder<'a> {
+    type Item = &'a SceneNode;
+    fn next(&mut self) -> Option<&'a SceneNode> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.iter().rev().map(|&child| unsafe { &*child }));
+        Some(node)
+    }
+}
+
+pub struct PostOrder<'a> {
+    stack: Vec<(&'a SceneNode, bool)>, // The bool tells whether the node's children have been pushed already
+}
+
+impl<'a> Iterator for PostOrder<'a> {
+    type Item = &'a SceneNode;
+    fn next(&mut self) -> Option<&'a SceneNode> {
+        loop {
+            let (node, expanded) = self.stack.pop()?;
+            if expanded || node.children.is_empty() {
+                return Some(node);
+            }
+            self.stack.push((node, true));
+            self.stack.extend(node.children.iter().rev().map(|&child| (unsafe { &*child }, false)));
+        }
+    }
+}
+
+pub struct BreadthFirst<'a> {
+    queue: VecDeque<&'a SceneNode>,
+}
+
+impl<'a> Iterator for BreadthFirst<'a> {
+    type Item = &'a SceneNode;
+    fn next(&mut self) -> Option<&'a SceneNode> {
+        let node = self.queue.pop_front()?;
+        self.queue.extend(node.children.iter().map(|&child| unsafe { &*child }));
+        Some(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frustum;
+
+    fn approx_mat4(a: &glm::Mat4, b: &glm::Mat4) -> bool {
+        a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-5)
+    }
+
+    fn rotation_x(angle: f32) -> glm::Mat4 {
+        glm::rotation(angle, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    fn rotation_y(angle: f32) -> glm::Mat4 {
+        glm::rotation(angle, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    fn rotation_z(angle: f32) -> glm::Mat4 {
+        glm::rotation(angle, &glm::vec3(0.0, 0.0, 1.0))
+    }
+
+    #[test]
+    fn quaternions_match_rotation_matrices() {
+        let axis = glm::normalize(&glm::vec3(1.0, 2.0, -0.5));
+        assert!(approx_mat4(&glm::quat_to_mat4(&glm::quat_angle_axis(0.8, &axis)), &glm::rotation(0.8, &axis)));
+
+        let mut node = SceneNode::new();
+        node.set_axis_angle(&glm::zero(), 1.0); //No axis, no rotation
+        assert!(approx_mat4(&glm::quat_to_mat4(&node.orientation), &glm::identity()));
+    }
+
+    #[test]
+    fn euler_orders_match_composed_rotations() {
+        let (x, y, z) = (0.3, -1.1, 2.0);
+        let (rx, ry, rz) = (rotation_x(x), rotation_y(y), rotation_z(z));
+        for &(order, expected) in &[
+            (EulerOrder::Xyz, rx * ry * rz),
+            (EulerOrder::Xzy, rx * rz * ry),
+            (EulerOrder::Yxz, ry * rx * rz),
+            (EulerOrder::Yzx, ry * rz * rx),
+            (EulerOrder::Zxy, rz * rx * ry),
+            (EulerOrder::Zyx, rz * ry * rx),
+        ] {
+            let mut node = SceneNode::new();
+            node.set_euler(&glm::vec3(x, y, z), order);
+            assert!(approx_mat4(&glm::quat_to_mat4(&node.orientation), &expected), "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn look_along_matches_look_at_view() {
+        //A view matrix turns the world so the camera looks down -z, so it is the inverse of the camera's orientation
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        for direction in &[glm::vec3(0.0, 0.0, -1.0), glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.3, -0.8, 0.5), glm::vec3(-2.0, 1.0, 3.0)] {
+            let view = glm::look_at(&glm::zero(), direction, &up);
+            let orientation = glm::quat_to_mat4(&quat_look_along(direction, &up));
+            assert!(approx_mat4(&orientation, &glm::transpose(&view)), "{:?}", direction);
+        }
+        //Turning right is a negative rotation about y
+        assert!(approx_mat4(&glm::quat_to_mat4(&quat_look_along(&glm::vec3(1.0, 0.0, 0.0), &up)), &rotation_y(-std::f32::consts::FRAC_PI_2)));
+    }
+
+    #[test]
+    fn look_along_up_is_not_nan() {
+        let up = glm::vec3(0.0, 1.0, 0.0);
+        for direction in &[up, -up, up * 5.0] {
+            let forward = glm::quat_rotate_vec3(&quat_look_along(direction, &up), &glm::vec3(0.0, 0.0, -1.0));
+            assert!(glm::distance(&forward, &glm::normalize(direction)) < 1e-5, "{:?}", direction);
+        }
+        let forward = glm::quat_rotate_vec3(&quat_look_along(&glm::vec3(1.0, 0.0, 0.0), &glm::vec3(1.0, 0.0, 0.0)), &glm::vec3(0.0, 0.0, -1.0));
+        assert!(glm::distance(&forward, &glm::vec3(1.0, 0.0, 0.0)) < 1e-5);
+        assert_eq!(quat_look_along(&glm::zero(), &up), glm::quat_identity());
+    }
+
+    fn named(name: &str) -> Node {
+        let mut node = SceneNode::new();
+        node.name = name.to_string();
+        node
+    }
+
+    // root -> a -> (leaf_1, leaf_2), and root -> b -> c
+    fn tree() -> (Node, Node, Node, Node, Node, Node) {
+        let (mut root, mut a, leaf_1, leaf_2, mut b, c) = (named("root"), named("a"), named("leaf_1"), named("leaf_2"), named("b"), named("c"));
+        a.position = glm::vec3(1.0, 0.0, 0.0);
+        b.position = glm::vec3(0.0, 2.0, 0.0);
+        a.add_child(&leaf_1);
+        a.add_child(&leaf_2);
+        b.add_child(&c);
+        root.add_child(&a);
+        root.add_child(&b);
+        (root, a, leaf_1, leaf_2, b, c)
+    }
+
+    #[test]
+    fn first_update_computes_every_node() {
+        let (mut root, ..) = tree();
+        assert_eq!(root.update_transformations(&glm::identity(), false, 1.0), 6);
+        assert_eq!(root.update_transformations(&glm::identity(), false, 1.0), 0);
+    }
+
+    #[test]
+    fn moving_a_leaf_only_recomputes_that_leaf() {
+        let (mut root, a, mut leaf_1, leaf_2, b, c) = tree();
+        root.update_transformations(&glm::identity(), false, 1.0);
+        let before: Vec<glm::Mat4> = [&a, &leaf_2, &b, &c].iter().map(|node| node.current_transformation_matrix).collect();
+
+        leaf_1.position = glm::vec3(0.0, 0.0, 3.0);
+        assert_eq!(root.update_transformations(&glm::identity(), false, 1.0), 1);
+        let after: Vec<glm::Mat4> = [&a, &leaf_2, &b, &c].iter().map(|node| node.current_transformation_matrix).collect();
+        assert_eq!(before, after);
+        assert_eq!(frustum::transform_point(&leaf_1.current_transformation_matrix, &glm::zero()), glm::vec3(1.0, 0.0, 3.0));
+    }
+
+    #[test]
+    fn moving_a_parent_recomputes_its_subtree() {
+        let (mut root, mut a, leaf_1, _leaf_2, b, c) = tree();
+        root.update_transformations(&glm::identity(), false, 1.0);
+        let (b_before, c_before) = (b.current_transformation_matrix, c.current_transformation_matrix);
+
+        a.position = glm::vec3(5.0, 0.0, 0.0);
+        assert_eq!(root.update_transformations(&glm::identity(), false, 1.0), 3);
+        assert_eq!((b.current_transformation_matrix, c.current_transformation_matrix), (b_before, c_before));
+        assert_eq!(frustum::transform_point(&leaf_1.current_transformation_matrix, &glm::zero()), glm::vec3(5.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn only_moving_nodes_are_blended_between_steps() {
+        let (mut root, mut a, leaf_1, _leaf_2, b, c) = tree();
+        root.update_transformations(&glm::identity(), false, 1.0);
+        let (b_before, c_before) = (b.current_transformation_matrix, c.current_transformation_matrix);
+
+        root.store_previous_transforms();
+        a.position = glm::vec3(3.0, 0.0, 0.0);
+        assert_eq!(root.update_transformations(&glm::identity(), false, 0.5), 3);
+        assert_eq!(frustum::transform_point(&leaf_1.current_transformation_matrix, &glm::zero()), glm::vec3(2.0, 0.0, 0.0));
+        assert_eq!((b.current_transformation_matrix, c.current_transformation_matrix), (b_before, c_before));
+        assert_eq!(root.update_transformations(&glm::identity(), false, 0.75), 3);
+        assert_eq!(root.update_transformations(&glm::identity(), false, 1.0), 3);
+        assert_eq!(root.update_transformations(&glm::identity(), false, 0.25), 3);
+    }
+
+    #[test]
+    fn traversal_orders() {
+        let (root, ..) = tree();
+        let names = |nodes: Vec<&SceneNode>| nodes.iter().map(|node| node.name.as_str()).collect::<Vec<_>>().join(" ");
+        assert_eq!(names(root.pre_order().collect()), "root a leaf_1 leaf_2 b c");
+        assert_eq!(names(root.post_order().collect()), "leaf_1 leaf_2 a c b root");
+        assert_eq!(names(root.breadth_first().collect()), "root a b leaf_1 leaf_2 c");
+    }
+}
@@ -1,4 +1,3 @@
-use gl;
 use std::{
     ptr,
     str,
@@ -13,6 +12,7 @@ pub struct Shader {
 pub struct ShaderBuilder {
     program_id: u32,
     shaders: Vec::<u32>,
+    defines: Vec<String>, // Added to the start of every shader compiled after they are given
 }
 
 #[allow(dead_code)]
@@ -35,9 +35,9 @@ impl Shader {
     }
 }
 
-impl Into<gl::types::GLenum> for ShaderType {
-    fn into(self) -> gl::types::GLenum {
-        match self {
+impl From<ShaderType> for gl::types::GLenum {
+    fn from(shader_type: ShaderType) -> gl::types::GLenum {
+        match shader_type {
             ShaderType::Vertex                  => { gl::VERTEX_SHADER          },
             ShaderType::Fragment                => { gl::FRAGMENT_SHADER        },
             ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
@@ -65,16 +65,23 @@ impl ShaderBuilder {
         ShaderBuilder {
             program_id: gl::CreateProgram(),
             shaders: vec![],
+            defines: vec![],
         }
     }
 
+    // #define name value in the shaders attached after this, e.g. to size arrays from Rust
+    pub fn define(mut self, name: &str, value: &str) -> ShaderBuilder {
+        self.defines.push(format!("#define {} {}\n", name, value));
+        self
+    }
+
     pub unsafe fn attach_file(self, shader_path: &str) -> ShaderBuilder {
         let path = Path::new(shader_path);
         if let Some(extension) = path.extension() {
             let shader_type = ShaderType::from_ext(extension)
                 .expect("Failed to parse file extension.");
             let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
+                .unwrap_or_else(|_| panic!("Failed to read shader source. {}", shader_path));
             self.compile_shader(&shader_src, shader_type)
         } else {
             panic!("Failed to read extension of file with path: {}", shader_path);
@@ -83,6 +90,11 @@ impl ShaderBuilder {
 
     pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
         let shader = gl::CreateShader(shader_type.into());
+        //The defines have to come after the #version line, which has to be first
+        let shader_src = match shader_src.split_once('\n') {
+            Some((version, rest)) if version.starts_with("#version") => format!("{}\n{}{}", version, self.defines.concat(), rest),
+            _ => format!("{}{}", self.defines.concat(), shader_src),
+        };
         let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
         gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
         gl::CompileShader(shader);
@@ -98,8 +110,7 @@ impl ShaderBuilder {
 
     unsafe fn check_shader_errors(&self, shader_id: u32) -> bool {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
+        let mut info_log = vec![0u8; 512];
         gl::GetShaderiv(shader_id, gl::COMPILE_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
             gl::GetShaderInfoLog(
@@ -116,8 +127,7 @@ impl ShaderBuilder {
 
     unsafe fn check_linker_errors(&self) -> bool {
         let mut success = i32::from(gl::FALSE);
-        let mut info_log = Vec::with_capacity(512);
-        info_log.set_len(512 - 1);
+        let mut info_log = vec![0u8; 512];
         gl::GetProgramiv(self.program_id, gl::LINK_STATUS, &mut success);
         if success != i32::from(gl::TRUE) {
             gl::GetProgramInfoLog(
@@ -0,0 +1,267 @@
+extern crate nalgebra_glm as glm;
+
+use std::ptr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::frustum::BoundingBox;
+use crate::shader::Shader;
+
+// Shadows from the first directional light. The scene is first drawn from the light into a depth texture, the
+// shadow map, and simple.frag then looks up whether anything was closer to the light than the fragment it lights.
+//
+// The shadow map only covers the part of the scene around the camera, see fit_light_space.
+
+// Texture unit the shadow map is bound to while drawing, simple.frag samples it from there
+const SHADOW_TEXTURE_UNIT: u32 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Shadows {
+    pub enabled: bool,
+    pub resolution: u32, // Width and height of the shadow map in texels
+    pub distance: f32, // How far from the camera shadows are drawn
+    pub bias: f32, // How far surfaces are moved towards the light before testing, to keep them from shadowing themselves
+    pub slope_bias: f32, // Extra bias for surfaces at a steep angle to the light, which need more
+    pub filter_radius: u32, // Percentage closer filtering, averages (2r + 1)^2 texels around the fragment for softer edges
+}
+
+impl Default for Shadows {
+    fn default() -> Shadows {
+        Shadows { enabled: true, resolution: 2048, distance: 150.0, bias: 0.2, slope_bias: 1.5, filter_radius: 1 }
+    }
+}
+
+// Where the shadow map is drawn from
+#[derive(Clone, Copy, Debug)]
+pub struct LightSpace {
+    pub view_projection: glm::Mat4, // Row vector convention, like the camera's
+    pub depth_range: f32, // World units between the near and far planes, to turn the bias into depth
+}
+
+// Point on the camera's frustum, from normalized device coordinates
+fn unproject(inverse_view_projection: &glm::Mat4, x: f32, y: f32, z: f32) -> glm::Vec3 {
+    let v = glm::transpose(inverse_view_projection) * glm::vec4(x, y, z, 1.0);
+    glm::vec3(v.x, v.y, v.z) / v.w
+}
+
+// An orthographic view along the light direction that covers what the camera sees up to shadows.distance away.
+// The covered area is the bounding sphere of that part of the frustum, so it keeps the same size when the camera
+// turns, and it moves in steps of whole texels so the shadow edges do not crawl when the camera moves. It is
+// stretched towards the light to the edge of scene_bounds, since things outside the view can cast shadows into it.
+pub fn fit_light_space(camera_view_projection: &glm::Mat4, camera_position: &glm::Vec3, near: f32, shadows: &Shadows, light_direction: &glm::Vec3, scene_bounds: Option<&BoundingBox>) -> LightSpace {
+    //The corners are found along the rays through the corners of the screen, instead of by unprojecting depths.
+    //The depth the camera's projection gives the far plane can be behind the camera, but the depths from -1 to 0
+    //are in front of it whichever way the projection is set up
+    let inverse = glm::inverse(camera_view_projection);
+    let mut corners = vec![];
+    for &(x, y) in &[(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+        let ray = glm::normalize(&(unproject(&inverse, x, y, 0.0) - unproject(&inverse, x, y, -1.0)));
+        corners.push(camera_position + ray * near);
+        corners.push(camera_position + ray * shadows.distance.max(near));
+    }
+    let center = corners.iter().fold(glm::zero(), |sum: glm::Vec3, corner| sum + corner) / corners.len() as f32;
+    let radius = corners.iter().map(|corner| glm::distance(corner, &center)).fold(0.0, f32::max).ceil();
+
+    //Looking along the light from the origin, so the center can be snapped to the texel grid
+    let direction = glm::normalize(light_direction);
+    let up = if direction.y.abs() > 0.99 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+    let view = glm::look_at(&glm::zero(), &direction, &up);
+    let texel = 2.0 * radius / shadows.resolution.max(1) as f32;
+    let center = (view * glm::vec4(center.x, center.y, center.z, 1.0)).xyz();
+    let center = glm::vec3((center.x / texel).floor() * texel, (center.y / texel).floor() * texel, center.z);
+
+    //The view looks down the negative z-axis, so larger z is closer to the light
+    let mut closest = center.z + radius;
+    if let Some(bounds) = scene_bounds {
+        for corner in bounds.corners().iter() {
+            closest = closest.max((view * glm::vec4(corner.x, corner.y, corner.z, 1.0)).z);
+        }
+    }
+    let (near_plane, far_plane) = (-closest - 1.0, -(center.z - radius));
+    let projection = glm::ortho(center.x - radius, center.x + radius, center.y - radius, center.y + radius, near_plane, far_plane);
+    LightSpace { view_projection: glm::transpose(&(projection * view)), depth_range: far_plane - near_plane }
+}
+
+// The depth texture and the framebuffer it is attached to
+pub struct ShadowMap {
+    pub resolution: u32,
+    framebuffer: u32,
+    texture: u32,
+    saved_framebuffer: i32, // What begin replaced, for end to put back
+    saved_viewport: [i32; 4],
+}
+
+impl ShadowMap {
+    pub unsafe fn new(resolution: u32) -> ShadowMap {
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH_COMPONENT24 as i32, resolution as i32, resolution as i32, 0, gl::DEPTH_COMPONENT, gl::FLOAT, ptr::null());
+        //Compare in the sampler, with linear filtering that already blends the results of the four nearest texels
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+        //Outside the shadow map everything is lit
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER as i32);
+        gl::TexParameterfv(gl::TEXTURE_2D, gl::TEXTURE_BORDER_COLOR, [1.0f32; 4].as_ptr());
+
+        let mut framebuffer = 0;
+        gl::GenFramebuffers(1, &mut framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, texture, 0);
+        gl::DrawBuffer(gl::NONE); //Depth only
+        gl::ReadBuffer(gl::NONE);
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            println!("The shadow map framebuffer is incomplete, shadows will be missing");
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        ShadowMap { resolution, framebuffer, texture, saved_framebuffer: 0, saved_viewport: [0; 4] }
+    }
+
+    // Start drawing into the shadow map. Everything drawn until end only writes depth
+    pub unsafe fn begin(&mut self) {
+        gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut self.saved_framebuffer);
+        gl::GetIntegerv(gl::VIEWPORT, self.saved_viewport.as_mut_ptr());
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+        gl::Viewport(0, 0, self.resolution as i32, self.resolution as i32);
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+    }
+
+    // Go back to drawing where we were before begin
+    pub unsafe fn end(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.saved_framebuffer as u32);
+        let [x, y, width, height] = self.saved_viewport;
+        gl::Viewport(x, y, width, height);
+    }
+
+    pub unsafe fn bind_texture(&self) {
+        gl::ActiveTexture(gl::TEXTURE0 + SHADOW_TEXTURE_UNIT);
+        gl::BindTexture(gl::TEXTURE_2D, self.texture);
+    }
+}
+
+// The shadow uniforms of a shader that uses simple.frag
+pub struct ShadowUniforms {
+    shadow_map: i32,
+    light_space: i32,
+    shadow_light: i32,
+    bias: i32,
+    slope_bias: i32,
+    filter_radius: i32,
+}
+
+impl ShadowUniforms {
+    pub unsafe fn new(shader: &Shader) -> ShadowUniforms {
+        ShadowUniforms {
+            shadow_map: shader.get_uniform_location("shadowMap"),
+            light_space: shader.get_uniform_location("lightSpaceMatrix"),
+            shadow_light: shader.get_uniform_location("shadowLight"),
+            bias: shader.get_uniform_location("shadowBias"),
+            slope_bias: shader.get_uniform_location("shadowSlopeBias"),
+            filter_radius: shader.get_uniform_location("shadowFilterRadius"),
+        }
+    }
+
+    // Make the light with the given index in the uploaded lights cast shadows from the shadow map, or turn shadows
+    // off with None. The shader has to be active
+    pub unsafe fn upload(&self, shadows: &Shadows, caster: Option<(usize, &LightSpace)>) {
+        gl::Uniform1i(self.shadow_map, SHADOW_TEXTURE_UNIT as i32);
+        match caster {
+            Some((light, light_space)) => {
+                gl::Uniform1i(self.shadow_light, light as i32);
+                gl::UniformMatrix4fv(self.light_space, 1, gl::FALSE, light_space.view_projection.as_ptr());
+                gl::Uniform1f(self.bias, shadows.bias / light_space.depth_range);
+                gl::Uniform1f(self.slope_bias, shadows.slope_bias / light_space.depth_range);
+                gl::Uniform1i(self.filter_radius, shadows.filter_radius as i32);
+            },
+            None => gl::Uniform1i(self.shadow_light, -1),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frustum::transform_point;
+
+    const NEAR: f32 = 1.0;
+
+    // A camera at eye looking at target with a 1 radian square field of view, in the row vector convention
+    fn camera(eye: &glm::Vec3, target: &glm::Vec3) -> glm::Mat4 {
+        glm::transpose(&(glm::perspective(1.0, 1.0, NEAR, 1000.0) * glm::look_at(eye, target, &glm::vec3(0.0, 1.0, 0.0))))
+    }
+
+    // Points along the rays through the corners and the middle of the screen, up to distance from the eye
+    fn frustum_points(eye: &glm::Vec3, target: &glm::Vec3, distance: f32) -> Vec<glm::Vec3> {
+        let to_world = glm::inverse(&glm::look_at(eye, target, &glm::vec3(0.0, 1.0, 0.0)));
+        let half = 0.5f32.tan();
+        let mut points = vec![];
+        for &(x, y) in &[(-half, -half), (half, -half), (-half, half), (half, half), (0.0, 0.0)] {
+            let ray = glm::normalize(&(to_world * glm::vec4(x, y, -1.0, 0.0)).xyz());
+            for &along in &[NEAR, distance / 2.0, distance] {
+                points.push(eye + ray * along);
+            }
+        }
+        points
+    }
+
+    fn inside(light_space: &LightSpace, point: &glm::Vec3) -> bool {
+        let p = transform_point(&light_space.view_projection, point);
+        p.x.abs() <= 1.0 && p.y.abs() <= 1.0 && p.z.abs() <= 1.0
+    }
+
+    #[test]
+    fn covers_what_the_camera_sees() {
+        let shadows = Shadows { distance: 80.0, ..Shadows::default() };
+        let light = glm::vec3(-1.0, -2.0, -0.5);
+        for &(eye, target) in &[
+            (glm::vec3(0.0, 10.0, 30.0), glm::vec3(0.0, 0.0, 0.0)),
+            (glm::vec3(100.0, 50.0, -20.0), glm::vec3(90.0, 0.0, 40.0)),
+            (glm::vec3(0.0, 5.0, 0.0), glm::vec3(0.0, 60.0, 1.0)), //Looking nearly straight up
+        ] {
+            let light_space = fit_light_space(&camera(&eye, &target), &eye, NEAR, &shadows, &light, None);
+            for point in frustum_points(&eye, &target, shadows.distance) {
+                assert!(inside(&light_space, &point), "{:?} seen from {:?} is outside the shadow map", point, eye);
+            }
+        }
+    }
+
+    #[test]
+    fn reaches_back_to_the_scene_bounds() {
+        let (eye, target) = (glm::vec3(0.0, 10.0, 30.0), glm::vec3(0.0, 0.0, 0.0));
+        let shadows = Shadows { distance: 50.0, ..Shadows::default() };
+        let light = glm::vec3(0.0, -1.0, 0.0);
+        let tower = BoundingBox { min: glm::vec3(-2.0, 0.0, -2.0), max: glm::vec3(2.0, 400.0, 2.0) }; //Much taller than the area the camera sees
+        let top = glm::vec3(0.0, 399.0, 0.0);
+
+        let without = fit_light_space(&camera(&eye, &target), &eye, NEAR, &shadows, &light, None);
+        assert!(!inside(&without, &top));
+        let with = fit_light_space(&camera(&eye, &target), &eye, NEAR, &shadows, &light, Some(&tower));
+        assert!(inside(&with, &top));
+        assert!(with.depth_range > without.depth_range);
+    }
+
+    #[test]
+    fn moves_in_whole_texels() {
+        let shadows = Shadows { resolution: 512, distance: 60.0, ..Shadows::default() };
+        let light = glm::vec3(-1.0, -2.0, -0.5);
+        let fit = |offset: glm::Vec3| {
+            let (eye, target) = (glm::vec3(10.0, 20.0, 30.0) + offset, glm::vec3(10.0, 0.0, 0.0) + offset);
+            fit_light_space(&camera(&eye, &target), &eye, NEAR, &shadows, &light, None)
+        };
+        let before = fit(glm::zero());
+        let point = glm::vec3(3.0, 1.0, -4.0);
+        for &offset in &[glm::vec3(0.3, 0.0, 0.0), glm::vec3(0.0, 0.0, -1.7), glm::vec3(2.2, 0.5, 0.9)] {
+            let after = fit(offset);
+            let shift = (transform_point(&after.view_projection, &point) - transform_point(&before.view_projection, &point)).xy() * shadows.resolution as f32 / 2.0;
+            for texels in shift.iter() {
+                assert!((texels - texels.round()).abs() < 0.01, "moved {:?} texels for {:?}", shift, offset);
+            }
+        }
+    }
+}
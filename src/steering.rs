@@ -0,0 +1,325 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{Deserialize, Serialize};
+
+use crate::behaviour::{Behaviour, BehaviourDescription, FrameContext};
+use crate::scene_graph::{quat_from_euler, EulerOrder, SceneNode};
+
+// Steering for groups of helicopters. The functions here only work on positions and velocities, so they can be
+// run without a scene graph. FormationBehaviour and FlockBehaviour use them to steer the children of the node they
+// are attached to, so the leader and followers, or the flock members, should share that parent.
+
+const GRAVITY: f32 = 9.81;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Agent {
+    pub position: glm::Vec3,
+    pub velocity: glm::Vec3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormationParameters {
+    pub catch_up: f32, // How hard followers steer towards their slots, per second
+    pub max_speed: f32,
+    pub max_acceleration: f32,
+    pub separation_radius: f32, // Followers closer to each other than this steer apart
+    pub separation_weight: f32,
+}
+
+impl Default for FormationParameters {
+    fn default() -> FormationParameters {
+        FormationParameters { catch_up: 2.0, max_speed: 60.0, max_acceleration: 80.0, separation_radius: 6.0, separation_weight: 20.0 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlockParameters {
+    pub cruise_speed: f32, // Members speed up when slower than this
+    pub max_speed: f32,
+    pub max_acceleration: f32,
+    pub separation_radius: f32,
+    pub separation_weight: f32,
+    pub neighbour_radius: f32, // How far members see the others they align with and stay close to
+    pub alignment_weight: f32,
+    pub cohesion_weight: f32,
+    pub center: [f32; 3], // The flock is steered back when it strays further than radius from center
+    pub radius: f32,
+    pub floor: f32, // Lowest height members fly at, so they do not dive into the ground
+}
+
+impl Default for FlockParameters {
+    fn default() -> FlockParameters {
+        FlockParameters {
+            cruise_speed: 10.0,
+            max_speed: 20.0,
+            max_acceleration: 15.0,
+            separation_radius: 10.0,
+            separation_weight: 60.0,
+            neighbour_radius: 50.0,
+            alignment_weight: 1.0,
+            cohesion_weight: 0.5,
+            center: [0.0, 10.0, 0.0],
+            radius: 60.0,
+            floor: 5.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FormationSlot {
+    pub node: String, // Path to the follower
+    pub offset: [f32; 3], // Where the follower flies, in the leader's model space
+}
+
+fn limit(v: glm::Vec3, max_length: f32) -> glm::Vec3 {
+    let length = glm::length(&v);
+    if length > max_length { v * (max_length / length) } else { v }
+}
+
+// Push away from every other agent closer than radius, harder the closer they are
+pub fn separation(index: usize, agents: &[Agent], radius: f32) -> glm::Vec3 {
+    let me = &agents[index];
+    agents.iter().enumerate()
+        .filter(|&(i, _)| i != index)
+        .fold(glm::zero(), |push: glm::Vec3, (_, other)| {
+            let away = me.position - other.position;
+            let distance = glm::length(&away);
+            if distance >= radius {
+                push
+            } else if distance < 1e-4 {
+                push + glm::vec3(1.0, 0.0, 0.0) * (index as f32 - 0.5).signum() //Right on top of each other, so pick a side
+            } else {
+                push + away / distance * (1.0 - distance / radius)
+            }
+        })
+}
+
+// Acceleration that brings agents[index] to its slot and keeps it there while the leader moves with leader_velocity
+pub fn formation_steering(index: usize, agents: &[Agent], slot: &glm::Vec3, leader_velocity: &glm::Vec3, parameters: &FormationParameters) -> glm::Vec3 {
+    let agent = &agents[index];
+    let desired_velocity = limit(leader_velocity + (slot - agent.position) * parameters.catch_up, parameters.max_speed);
+    let steering = (desired_velocity - agent.velocity) * parameters.catch_up * 2.0
+        + separation(index, agents, parameters.separation_radius) * parameters.separation_weight;
+    limit(steering, parameters.max_acceleration)
+}
+
+// Acceleration of agents[index] in a boids flock: keep apart, fly the same way as the neighbours, stay close to
+// them, keep up cruising speed and stay inside the flock's area
+pub fn flocking_steering(index: usize, agents: &[Agent], parameters: &FlockParameters) -> glm::Vec3 {
+    let agent = &agents[index];
+    let neighbours: Vec<&Agent> = agents.iter().enumerate()
+        .filter(|&(i, other)| i != index && glm::distance(&other.position, &agent.position) < parameters.neighbour_radius)
+        .map(|(_, other)| other)
+        .collect();
+
+    let mut steering = separation(index, agents, parameters.separation_radius) * parameters.separation_weight;
+    if !neighbours.is_empty() {
+        let count = neighbours.len() as f32;
+        let average_velocity = neighbours.iter().fold(glm::zero(), |sum: glm::Vec3, other| sum + other.velocity) / count;
+        let center = neighbours.iter().fold(glm::zero(), |sum: glm::Vec3, other| sum + other.position) / count;
+        steering += (average_velocity - agent.velocity) * parameters.alignment_weight;
+        steering += (center - agent.position) * parameters.cohesion_weight;
+    }
+
+    let from_center = agent.position - glm::make_vec3(&parameters.center);
+    let outside = glm::length(&from_center) - parameters.radius;
+    if outside > 0.0 {
+        steering -= glm::normalize(&from_center) * outside;
+    }
+    if agent.position.y < parameters.floor {
+        steering.y += (parameters.floor - agent.position.y) * 4.0 - agent.velocity.y.min(0.0) * 4.0;
+    }
+
+    let speed = glm::length(&agent.velocity);
+    if speed < parameters.cruise_speed {
+        let direction = if speed > 1e-4 { agent.velocity / speed } else { glm::vec3(0.0, 0.0, -1.0) };
+        steering += direction * (parameters.cruise_speed - speed);
+    }
+    limit(steering, parameters.max_acceleration)
+}
+
+// Move an agent delta_time seconds with the given acceleration
+pub fn integrate(agent: &mut Agent, acceleration: &glm::Vec3, max_speed: f32, delta_time: f32) {
+    agent.velocity = limit(agent.velocity + acceleration * delta_time, max_speed);
+    agent.position += agent.velocity * delta_time;
+}
+
+// Which way something flying with the given velocity and acceleration faces: along the velocity, banked into turns
+pub fn orientation_from_motion(velocity: &glm::Vec3, acceleration: &glm::Vec3) -> Option<glm::Quat> {
+    let horizontal = glm::vec3(velocity.x, 0.0, velocity.z);
+    if glm::length(&horizontal) < 1e-3 {
+        return None;
+    }
+    let left = glm::normalize(&glm::cross(&glm::vec3(0.0, 1.0, 0.0), &horizontal));
+    let yaw = std::f32::consts::PI + velocity.x.atan2(velocity.z);
+    let pitch = velocity.y.atan2(glm::length(&horizontal));
+    let roll = (glm::dot(acceleration, &left) / GRAVITY).atan();
+    Some(quat_from_euler(&glm::vec3(pitch, yaw, roll), EulerOrder::Yxz))
+}
+
+// Keeps the followers in their slots around the leader. The leader is found by its path and flies on its own
+pub struct FormationBehaviour {
+    pub leader: String,
+    pub slots: Vec<FormationSlot>,
+    pub parameters: FormationParameters,
+    agents: Vec<Agent>,
+    leader_position: Option<glm::Vec3>, // Where the leader was last update
+}
+
+impl FormationBehaviour {
+    pub fn new(leader: &str, slots: Vec<FormationSlot>, parameters: FormationParameters) -> FormationBehaviour {
+        FormationBehaviour { leader: leader.to_string(), slots, parameters, agents: vec![], leader_position: None }
+    }
+}
+
+impl Behaviour for FormationBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        let (leader_position, leader_orientation) = match node.find(&self.leader) {
+            Some(leader) => (leader.position, leader.orientation),
+            None => return,
+        };
+        let leader_velocity = match self.leader_position {
+            Some(previous) if ctx.delta_time > 0.0 => (leader_position - previous) / ctx.delta_time,
+            _ => glm::zero(),
+        };
+        self.leader_position = Some(leader_position);
+
+        if self.agents.len() != self.slots.len() {
+            self.agents = self.slots.iter()
+                .map(|slot| Agent { position: node.find(&slot.node).map_or(leader_position, |n| n.position), velocity: leader_velocity })
+                .collect();
+        }
+
+        //The slots turn with the leader's heading, but not when it pitches or rolls, or they would swing up and down
+        let forward = glm::quat_rotate_vec3(&leader_orientation, &glm::vec3(0.0, 0.0, -1.0));
+        let heading = glm::quat_angle_axis(std::f32::consts::PI + forward.x.atan2(forward.z), &glm::vec3(0.0, 1.0, 0.0));
+
+        let agents = self.agents.clone(); //Everyone steers by where the others were at the start of the update
+        for (i, slot) in self.slots.iter().enumerate() {
+            let target = leader_position + glm::quat_rotate_vec3(&heading, &glm::make_vec3(&slot.offset));
+            let acceleration = formation_steering(i, &agents, &target, &leader_velocity, &self.parameters);
+            integrate(&mut self.agents[i], &acceleration, self.parameters.max_speed, ctx.delta_time);
+            if let Some(follower) = node.find(&slot.node) {
+                follower.position = self.agents[i].position;
+                follower.slerp_towards(&leader_orientation, (self.parameters.catch_up * ctx.delta_time).min(1.0));
+            }
+        }
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::Formation { leader: self.leader.clone(), slots: self.slots.clone(), parameters: self.parameters })
+    }
+}
+
+// Steers every node below this one with the given tag as a flock
+pub struct FlockBehaviour {
+    pub tag: String,
+    pub parameters: FlockParameters,
+    agents: Vec<Agent>,
+}
+
+impl FlockBehaviour {
+    pub fn new(tag: &str, parameters: FlockParameters) -> FlockBehaviour {
+        FlockBehaviour { tag: tag.to_string(), parameters, agents: vec![] }
+    }
+}
+
+impl Behaviour for FlockBehaviour {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        let mut members = node.find_by_tag(&self.tag);
+        if self.agents.len() != members.len() {
+            //Members start off flying the way they face
+            let cruise_speed = self.parameters.cruise_speed;
+            self.agents = members.iter()
+                .map(|member| Agent { position: member.position, velocity: glm::quat_rotate_vec3(&member.orientation, &glm::vec3(0.0, 0.0, -cruise_speed)) })
+                .collect();
+        }
+
+        let agents = self.agents.clone();
+        for (i, member) in members.iter_mut().enumerate() {
+            let acceleration = flocking_steering(i, &agents, &self.parameters);
+            integrate(&mut self.agents[i], &acceleration, self.parameters.max_speed, ctx.delta_time);
+            member.position = self.agents[i].position;
+            if let Some(orientation) = orientation_from_motion(&self.agents[i].velocity, &acceleration) {
+                member.slerp_towards(&orientation, (4.0 * ctx.delta_time).min(1.0));
+            }
+        }
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        Some(BehaviourDescription::Flock { tag: self.tag.clone(), parameters: self.parameters })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_graph::Node;
+
+    fn step(behaviour: &mut dyn Behaviour, node: &mut SceneNode, time: f32, delta_time: f32) {
+        behaviour.update(node, &FrameContext { time, delta_time, ..Default::default() });
+    }
+
+    fn child(parent: &mut SceneNode, name: &str, position: glm::Vec3) -> Node {
+        let mut node = SceneNode::new();
+        node.name = name.to_string();
+        node.position = position;
+        parent.add_child(&node);
+        node
+    }
+
+    #[test]
+    fn followers_catch_up_with_their_slots() {
+        let mut group = SceneNode::new();
+        let mut leader = child(&mut group, "leader", glm::zero());
+        let followers = [child(&mut group, "left", glm::vec3(-30.0, 5.0, 20.0)), child(&mut group, "right", glm::vec3(0.0, 0.0, 0.0))];
+        let slots = vec![
+            FormationSlot { node: "left".to_string(), offset: [-10.0, 0.0, 10.0] },
+            FormationSlot { node: "right".to_string(), offset: [10.0, 0.0, 10.0] },
+        ];
+        let mut formation = FormationBehaviour::new("leader", slots.clone(), FormationParameters::default());
+
+        //The leader flies forward, along -z, at 15 units per second
+        let delta_time = 1.0 / 60.0;
+        for frame in 0..600 {
+            leader.position.z -= 15.0 * delta_time;
+            step(&mut formation, &mut group, frame as f32 * delta_time, delta_time);
+        }
+        for (follower, slot) in followers.iter().zip(&slots) {
+            let target = leader.position + glm::make_vec3(&slot.offset);
+            assert!(glm::distance(&follower.position, &target) < 0.5, "{} is at {:?}, its slot at {:?}", follower.name, follower.position, target);
+        }
+    }
+
+    #[test]
+    fn flocks_stay_together_inside_their_area() {
+        let mut group = SceneNode::new();
+        let mut members = vec![];
+        for i in 0..8 {
+            let mut member = child(&mut group, &format!("bird_{}", i), glm::vec3((i % 4) as f32 * 3.0 - 40.0, 8.0 + i as f32, (i / 4) as f32 * 3.0));
+            member.tags.push("bird".to_string());
+            members.push(member);
+        }
+        let parameters = FlockParameters::default();
+        let mut flock = FlockBehaviour::new("bird", parameters);
+
+        let delta_time = 1.0 / 60.0;
+        for frame in 0..3600 {
+            step(&mut flock, &mut group, frame as f32 * delta_time, delta_time);
+        }
+        let center = glm::make_vec3(&parameters.center);
+        for member in &members {
+            assert!(glm::distance(&member.position, &center) < parameters.radius * 1.5, "{} strayed to {:?}", member.name, member.position);
+            assert!(member.position.y > 0.0, "{} is at {:?}", member.name, member.position);
+        }
+        //They have spread out from where they started right next to each other, but not too far
+        for (i, a) in members.iter().enumerate() {
+            for b in &members[i + 1..] {
+                let distance = glm::distance(&a.position, &b.position);
+                assert!(distance > 2.0 && distance < parameters.neighbour_radius * 2.0, "{} and {} are {} apart", a.name, b.name, distance);
+            }
+        }
+    }
+}
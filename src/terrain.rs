@@ -0,0 +1,106 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::mesh::Mesh;
+
+// How many grid cells the terrain is split into along its longest side
+const GRID_RESOLUTION: f32 = 64.0;
+
+// The triangles of a terrain mesh, sorted into a grid on the xz-plane so the height of the ground below a point can
+// be found without testing every triangle. Everything is in the model space of the mesh.
+pub struct Terrain {
+    triangles: Vec<[glm::Vec3; 3]>,
+    cells: HashMap<(i32, i32), Vec<usize>>, // Indices of the triangles that overlap each cell
+    cell_size: f32,
+}
+
+impl Terrain {
+    pub fn from_mesh(mesh: &Mesh) -> Terrain {
+        let vertex = |i: u32| glm::vec3(mesh.vertices[3 * i as usize], mesh.vertices[3 * i as usize + 1], mesh.vertices[3 * i as usize + 2]);
+        let triangles: Vec<[glm::Vec3; 3]> = mesh.indices.chunks_exact(3)
+            .map(|triangle| [vertex(triangle[0]), vertex(triangle[1]), vertex(triangle[2])])
+            .collect();
+
+        let extent = mesh.bounds.map_or(1.0, |b| (b.max.x - b.min.x).max(b.max.z - b.min.z));
+        let mut terrain = Terrain { triangles, cells: HashMap::new(), cell_size: (extent / GRID_RESOLUTION).max(1e-3) };
+
+        for (index, [a, b, c]) in terrain.triangles.iter().enumerate() {
+            let (min_x, min_z) = terrain.cell(a.x.min(b.x).min(c.x), a.z.min(b.z).min(c.z));
+            let (max_x, max_z) = terrain.cell(a.x.max(b.x).max(c.x), a.z.max(b.z).max(c.z));
+            for x in min_x..=max_x {
+                for z in min_z..=max_z {
+                    terrain.cells.entry((x, z)).or_default().push(index);
+                }
+            }
+        }
+        terrain
+    }
+
+    fn cell(&self, x: f32, z: f32) -> (i32, i32) {
+        ((x / self.cell_size).floor() as i32, (z / self.cell_size).floor() as i32)
+    }
+
+    // Height of the highest part of the terrain straight above or below the point (x, z), or None if the terrain
+    // does not reach that far
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let point = glm::vec2(x, z);
+        self.cells.get(&self.cell(x, z))?.iter()
+            .filter_map(|&index| {
+                let [a, b, c] = &self.triangles[index];
+                //Barycentric coordinates of the point in the triangle, projected onto the xz-plane
+                let (ab, ac, ap) = (glm::vec2(b.x - a.x, b.z - a.z), glm::vec2(c.x - a.x, c.z - a.z), point - glm::vec2(a.x, a.z));
+                let area = ab.x * ac.y - ab.y * ac.x;
+                if area.abs() < 1e-9 {
+                    return None; //Standing straight up, so it has no height to give
+                }
+                let u = (ap.x * ac.y - ap.y * ac.x) / area;
+                let v = (ab.x * ap.y - ab.y * ap.x) / area;
+                if u < 0.0 || v < 0.0 || u + v > 1.0 {
+                    return None;
+                }
+                Some(a.y + u * (b.y - a.y) + v * (c.y - a.y))
+            })
+            .fold(None, |highest: Option<f32>, height| Some(highest.map_or(height, |h| h.max(height))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frustum::BoundingBox;
+
+    // A 10 by 10 quad of two triangles, split along x + z = 10, with its corners at different heights
+    fn quad() -> Terrain {
+        let vertices = vec![
+            0.0, 0.0, 0.0,
+            10.0, 2.0, 0.0,
+            0.0, 4.0, 10.0,
+            10.0, 10.0, 10.0,
+        ];
+        let bounds = BoundingBox::from_vertices(&vertices);
+        Terrain::from_mesh(&Mesh { vertices, normals: vec![], colors: vec![], indices: vec![0, 1, 2, 1, 3, 2], index_count: 6, bounds })
+    }
+
+    fn approx(a: Option<f32>, b: f32) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-4)
+    }
+
+    #[test]
+    fn heights_inside_the_triangles() {
+        let terrain = quad();
+        assert!(approx(terrain.height_at(0.0, 0.0), 0.0));
+        assert!(approx(terrain.height_at(2.0, 2.0), 1.2)); //y = 0.2x + 0.4z in the first triangle
+        assert!(approx(terrain.height_at(8.0, 8.0), 7.2)); //y = 0.6x + 0.8z - 4 in the second
+        assert!(approx(terrain.height_at(5.0, 5.0), 3.0)); //On the diagonal both agree
+        assert!(approx(terrain.height_at(10.0, 10.0), 10.0));
+    }
+
+    #[test]
+    fn no_height_outside_the_terrain() {
+        let terrain = quad();
+        for &(x, z) in &[(-1.0, 5.0), (11.0, 5.0), (5.0, -0.5), (5.0, 20.0), (-100.0, -100.0)] {
+            assert_eq!(terrain.height_at(x, z), None, "{} {}", x, z);
+        }
+    }
+}
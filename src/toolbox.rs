@@ -1,37 +1,445 @@
 extern crate nalgebra_glm as glm;
 
+use serde::{de::Error, Deserialize, Deserializer, Serialize};
+
+use crate::animation::Interpolate;
+
+// Where something flying is and which way it is facing. Yaw is outermost, so pitch and roll are relative to
+// the direction it is flying in
 pub struct Heading {
     pub x: f32,
+    pub y: f32,
     pub z: f32,
     pub yaw: f32,
     pub pitch: f32,
     pub roll: f32,
 }
 
-pub fn simple_heading_animation(time: f32) -> Heading {
-    let t = time as f64;
-    let step = 0.05f64;
-    let path_size = 15f64;
-    let circuit_speed = 0.8f64;
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AltitudeProfile {
+    Constant(f32),
+    Wave { base: f32, amplitude: f32, period: f32 }, // Bob up and down around base, period is in seconds
+}
+
+impl Default for AltitudeProfile {
+    fn default() -> AltitudeProfile {
+        AltitudeProfile::Constant(0.0)
+    }
+}
+
+impl AltitudeProfile {
+    pub fn altitude(&self, time: f32) -> f32 {
+        match *self {
+            AltitudeProfile::Constant(altitude) => altitude,
+            AltitudeProfile::Wave { base, amplitude, period } => base + amplitude * (2.0 * std::f32::consts::PI * time / period).sin(),
+        }
+    }
+}
+
+// A figure eight, three times as long as it is wide
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FigureEight {
+    pub size: f32, // Half the width of the figure
+    pub speed: f32, // How fast to go around it, 2pi / speed is the time one lap takes
+    pub altitude: AltitudeProfile,
+    pub phase: f32, // Seconds ahead of others flying the same figure
+}
+
+impl Default for FigureEight {
+    fn default() -> FigureEight {
+        FigureEight { size: 15.0, speed: 0.8, altitude: AltitudeProfile::default(), phase: 0.0 }
+    }
+}
+
+impl FigureEight {
+    pub fn heading(&self, time: f32) -> Heading {
+        let t = (time + self.phase) as f64;
+        let step = 0.05f64;
+        let path_size = self.size as f64;
+        let circuit_speed = self.speed as f64;
+
+        let xpos = path_size*(2.0*t*circuit_speed).sin();
+        let nextxpos = path_size*(2.0*(t+step)*circuit_speed).sin();
+        let zpos = 3.0*path_size*(t*circuit_speed).cos();
+        let nextzpos = 3.0*path_size*((t+step)*circuit_speed).cos();
+
+        let delta_pos = glm::vec2(nextxpos-xpos, nextzpos-zpos);
+
+        let yaw = std::f64::consts::PI + delta_pos.x.atan2(delta_pos.y);
+        let pitch = -0.175 * glm::length(&delta_pos);
+        let roll = (t*circuit_speed).cos() * 0.5;
+        let altitude = self.altitude.altitude(t as f32);
+
+        Heading {
+            x: xpos as f32,
+            y: altitude,
+            z: zpos as f32,
+            yaw: yaw as f32,
+            pitch: pitch as f32,
+            roll: roll as f32,
+        }
+    }
+}
+
+const GRAVITY: f32 = 9.81;
+
+// Goes from 0 to 1 as t goes from 0 to 1, starting and stopping smoothly without any jerk. Returns the value
+// and its first and second derivatives
+fn smootherstep(t: f32) -> (f32, f32, f32) {
+    let t = t.clamp(0.0, 1.0);
+    let s = 1.0 - t;
+    (t * t * t * (t * (6.0 * t - 15.0) + 10.0), 30.0 * t * t * s * s, 60.0 * t * s * (1.0 - 2.0 * t))
+}
+
+// The top speed of a smootherstep is this many times its average speed
+const SMOOTHERSTEP_TOP_SPEED: f32 = 1.875;
+
+// A trip from one landing spot to another: take off straight up, hover, cruise over at a constant altitude, hover
+// above the destination, and land. Every move speeds up and slows down smoothly, and the nose dips while speeding up and
+// lifts while slowing down. Before the start and after landing it sits still on the ground.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FlightSequence {
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+    pub cruise_altitude: f32,
+    #[serde(deserialize_with = "positive_speed")]
+    pub cruise_speed: f32, // Top speed while cruising
+    #[serde(deserialize_with = "positive_speed")]
+    pub vertical_speed: f32, // Top speed while taking off and landing
+    pub hover_time: f32, // Seconds spent hovering before and after cruising
+}
+
+// The durations are worked out by dividing by the speeds, so a speed of 0 or less would never get anywhere
+fn positive_speed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+    let speed = f32::deserialize(deserializer)?;
+    if speed > 0.0 {
+        Ok(speed)
+    } else {
+        Err(D::Error::custom(format!("the speeds of a flight sequence have to be above 0, not {}", speed)))
+    }
+}
+
+impl FlightSequence {
+    fn climb_duration(&self, height: f32) -> f32 {
+        SMOOTHERSTEP_TOP_SPEED * height.abs() / self.vertical_speed
+    }
+
+    pub fn duration(&self) -> f32 {
+        let cruise_distance = glm::distance(&glm::vec2(self.from[0], self.from[2]), &glm::vec2(self.to[0], self.to[2]));
+        self.climb_duration(self.cruise_altitude - self.from[1])
+            + 2.0 * self.hover_time
+            + SMOOTHERSTEP_TOP_SPEED * cruise_distance / self.cruise_speed
+            + self.climb_duration(self.cruise_altitude - self.to[1])
+    }
+
+    pub fn heading(&self, time: f32) -> Heading {
+        let from = glm::make_vec3(&self.from);
+        let to = glm::make_vec3(&self.to);
+        let direction = glm::vec2(to.x - from.x, to.z - from.z);
+        let distance = glm::length(&direction);
+        let yaw = std::f32::consts::PI + direction.x.atan2(direction.y);
+
+        let takeoff = self.climb_duration(self.cruise_altitude - from.y);
+        let cruise = SMOOTHERSTEP_TOP_SPEED * distance / self.cruise_speed;
+        let landing = self.climb_duration(self.cruise_altitude - to.y);
+
+        // How far along the cruise we are, as a fraction of the distance, and the acceleration along it
+        let cruise_time = time - takeoff - self.hover_time;
+        let (travelled, acceleration) = if cruise > 0.0 {
+            let (s, _, dds) = smootherstep(cruise_time / cruise);
+            (s, distance * dds / (cruise * cruise))
+        } else {
+            (1.0, 0.0)
+        };
+
+        // Altitude, which is taking off, cruising or landing depending on the time
+        let landing_start = takeoff + 2.0 * self.hover_time + cruise;
+        let altitude = if time < landing_start {
+            let (s, _, _) = if takeoff > 0.0 { smootherstep(time / takeoff) } else { (1.0, 0.0, 0.0) };
+            from.y + (self.cruise_altitude - from.y) * s
+        } else {
+            let (s, _, _) = if landing > 0.0 { smootherstep((time - landing_start) / landing) } else { (1.0, 0.0, 0.0) };
+            self.cruise_altitude + (to.y - self.cruise_altitude) * s
+        };
+
+        let position = glm::lerp(&from, &to, travelled);
+        Heading {
+            x: position.x,
+            y: altitude,
+            z: position.z,
+            yaw,
+            pitch: -(acceleration / GRAVITY).atan(),
+            roll: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SplineKind {
+    CatmullRom, // Passes through every control point
+    Bezier, // Cubic segments, every third point is on the path and the two between them are handles
+}
+
+// Number of straight pieces each spline segment is split into when measuring its length
+const SAMPLES_PER_SEGMENT: usize = 32;
+// Distance between the points used to estimate the direction and curvature of a path
+const DERIVATIVE_STEP: f32 = 0.5;
+
+// A route through space, parameterized by the distance travelled along it so that following it at a constant
+// speed is just a matter of moving the distance forward at that speed.
+//
+// A Catmull-Rom path needs at least two points. A Bezier path needs 3n+1 points, or 3n when it is closed since
+// it then ends at the first point. Points that do not make up a whole segment are ignored.
+#[derive(Clone, Debug)]
+pub struct FlightPath {
+    points: Vec<glm::Vec3>,
+    kind: SplineKind,
+    closed: bool, // Closed paths loop back to the start, open paths stop at the end
+    lengths: Vec<f32>, // Distance along the path at each sample, SAMPLES_PER_SEGMENT per segment plus one at the end
+}
+
+impl FlightPath {
+    pub fn new(points: Vec<glm::Vec3>, kind: SplineKind, closed: bool) -> FlightPath {
+        let mut path = FlightPath { points, kind, closed, lengths: vec![0.0] };
 
-    let xpos = path_size*(2.0*t*circuit_speed).sin();
-    let nextxpos = path_size*(2.0*(t+step)*circuit_speed).sin();
-    let zpos = 3.0*path_size*(t*circuit_speed).cos();
-    let nextzpos = 3.0*path_size*((t+step)*circuit_speed).cos();
+        let mut length = 0.0;
+        let mut previous = path.segment_point(0, 0.0);
+        for segment in 0..path.segment_count() {
+            for sample in 1..=SAMPLES_PER_SEGMENT {
+                let point = path.segment_point(segment, sample as f32 / SAMPLES_PER_SEGMENT as f32);
+                length += glm::distance(&previous, &point);
+                path.lengths.push(length);
+                previous = point;
+            }
+        }
+        path
+    }
+
+    pub fn control_points(&self) -> &[glm::Vec3] {
+        &self.points
+    }
+
+    pub fn kind(&self) -> SplineKind {
+        self.kind
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn length(&self) -> f32 {
+        *self.lengths.last().unwrap()
+    }
+
+    // How far along the path segment i starts. Catmull-Rom segment i starts at control point i
+    pub fn segment_start(&self, segment: usize) -> f32 {
+        self.lengths[(segment * SAMPLES_PER_SEGMENT).min(self.lengths.len() - 1)]
+    }
+
+    fn segment_count(&self) -> usize {
+        let n = self.points.len();
+        match (self.kind, self.closed) {
+            (SplineKind::CatmullRom, true) if n >= 2 => n,
+            (SplineKind::CatmullRom, false) => n.saturating_sub(1),
+            (SplineKind::Bezier, true) if n >= 3 => n / 3,
+            (SplineKind::Bezier, false) => n.saturating_sub(1) / 3,
+            _ => 0,
+        }
+    }
+
+    // Control point i, wrapping around on closed paths and clamping to the ends on open ones
+    fn point(&self, i: isize) -> glm::Vec3 {
+        let n = self.points.len() as isize;
+        let i = if self.closed { i.rem_euclid(n) } else { i.max(0).min(n - 1) };
+        self.points[i as usize]
+    }
+
+    // The point a fraction t along a segment
+    fn segment_point(&self, segment: usize, t: f32) -> glm::Vec3 {
+        if self.segment_count() == 0 {
+            return self.points.first().copied().unwrap_or_else(glm::zero);
+        }
+        match self.kind {
+            SplineKind::CatmullRom => {
+                let i = segment as isize;
+                <glm::Vec3 as Interpolate>::cubic(&self.point(i - 1), &self.point(i), &self.point(i + 1), &self.point(i + 2), t)
+            },
+            SplineKind::Bezier => {
+                let i = 3 * segment as isize;
+                let s = 1.0 - t;
+                self.point(i) * (s * s * s) + self.point(i + 1) * (3.0 * s * s * t) + self.point(i + 2) * (3.0 * s * t * t) + self.point(i + 3) * (t * t * t)
+            },
+        }
+    }
+
+    // Wrap distances on closed paths and clamp them on open ones
+    fn wrap(&self, distance: f32) -> f32 {
+        let length = self.length();
+        if length <= 0.0 {
+            0.0
+        } else if self.closed {
+            distance.rem_euclid(length)
+        } else {
+            distance.max(0.0).min(length)
+        }
+    }
+
+    pub fn position_at(&self, distance: f32) -> glm::Vec3 {
+        let distance = self.wrap(distance);
+        if self.lengths.len() < 2 {
+            return self.segment_point(0, 0.0);
+        }
+        // lengths[sample] is the last sample at or before distance
+        let sample = (self.lengths.partition_point(|&l| l <= distance) - 1).min(self.lengths.len() - 2);
+        let (from, to) = (self.lengths[sample], self.lengths[sample + 1]);
+        let fraction = if to > from { (distance - from) / (to - from) } else { 0.0 };
+
+        let segment = sample / SAMPLES_PER_SEGMENT;
+        let t = ((sample % SAMPLES_PER_SEGMENT) as f32 + fraction) / SAMPLES_PER_SEGMENT as f32;
+        self.segment_point(segment, t)
+    }
+
+    // Where on the path we are after travelling the given distance along it, facing the way the path goes. The
+    // pitch follows the climb of the path, and the roll leans into turns as much as an aircraft flying at the given
+    // speed would in a coordinated turn.
+    pub fn heading_at(&self, distance: f32, speed: f32) -> Heading {
+        let position = self.position_at(distance);
 
-    let delta_pos = glm::vec2(nextxpos-xpos, nextzpos-zpos);
+        // Estimate the derivatives from points around the distance, moved inwards at the ends of open paths
+        let h = DERIVATIVE_STEP;
+        let d = if self.closed || self.length() < 2.0 * h { distance } else { distance.max(h).min(self.length() - h) };
+        let (behind, middle, ahead) = (self.position_at(d - h), self.position_at(d), self.position_at(d + h));
+        let tangent = (ahead - behind) / (2.0 * h);
+        let curvature = (ahead - middle * 2.0 + behind) / (h * h);
 
-    let yaw = std::f64::consts::PI + delta_pos.x.atan2(delta_pos.y);
-    let pitch = -0.175 * glm::length(&delta_pos);
-    let roll = (t*circuit_speed).cos() * 0.5;
+        let horizontal = glm::vec3(tangent.x, 0.0, tangent.z);
+        let left = glm::cross(&glm::vec3(0.0, 1.0, 0.0), &horizontal);
+        let left = if glm::length(&left) > 0.0 { glm::normalize(&left) } else { left };
 
-    Heading {
-        x: xpos as f32,
-        z: zpos as f32,
-        yaw: yaw as f32,
-        pitch: pitch as f32,
-        roll: roll as f32,
+        Heading {
+            x: position.x,
+            y: position.y,
+            z: position.z,
+            yaw: std::f32::consts::PI + tangent.x.atan2(tangent.z),
+            pitch: tangent.y.atan2(glm::length(&horizontal)),
+            roll: (speed * speed / GRAVITY * glm::dot(&curvature, &left)).atan(),
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
 
+    fn approx(a: f32, b: f32, tolerance: f32) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    // The direction in the xz plane a yaw faces, -z for a yaw of 0 like the models
+    fn facing(heading: &Heading) -> glm::Vec2 {
+        glm::vec2(-heading.yaw.sin(), -heading.yaw.cos())
+    }
+
+    #[test]
+    fn figure_eight_positions() {
+        let path = FigureEight::default();
+        let start = path.heading(0.0);
+        assert!(approx(start.x, 0.0, 1e-5) && approx(start.y, 0.0, 1e-5) && approx(start.z, 45.0, 1e-4));
+        assert!(approx(start.roll, 0.5, 1e-5));
+        let next = path.heading(0.01);
+        let moving = glm::normalize(&glm::vec2(next.x - start.x, next.z - start.z));
+        assert!(glm::distance(&facing(&start), &moving) < 0.05);
+
+        //The middle of the eight is a quarter of a lap in
+        let middle = path.heading(PI / 2.0 / path.speed);
+        assert!(approx(middle.x, 0.0, 1e-4) && approx(middle.z, 0.0, 1e-4));
+
+        let lap = 2.0 * PI / path.speed;
+        for &time in &[0.3, 1.7, 5.0] {
+            let (a, b) = (path.heading(time), path.heading(time + lap));
+            assert!(approx(a.x, b.x, 1e-3) && approx(a.z, b.z, 1e-3) && approx(a.yaw, b.yaw, 1e-3), "{}", time);
+        }
+    }
+
+    #[test]
+    fn figure_eight_phase_and_altitude() {
+        let path = FigureEight { altitude: AltitudeProfile::Wave { base: 10.0, amplitude: 2.0, period: 4.0 }, ..Default::default() };
+        let ahead = FigureEight { phase: 1.5, ..path };
+        let (a, b) = (path.heading(2.0), ahead.heading(0.5));
+        assert!(approx(a.x, b.x, 1e-5) && approx(a.z, b.z, 1e-5) && approx(a.y, b.y, 1e-5));
+
+        assert!(approx(path.heading(0.0).y, 10.0, 1e-4));
+        assert!(approx(path.heading(1.0).y, 12.0, 1e-4));
+        assert!(approx(path.heading(3.0).y, 8.0, 1e-4));
+    }
+
+    #[test]
+    fn straight_path_headings() {
+        let path = FlightPath::new(vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(10.0, 0.0, 0.0), glm::vec3(20.0, 0.0, 0.0)], SplineKind::CatmullRom, false);
+        assert!(approx(path.length(), 20.0, 1e-3));
+        assert!(approx(path.segment_start(1), 10.0, 1e-3));
+
+        let heading = path.heading_at(5.0, 10.0);
+        assert!(approx(heading.x, 5.0, 0.05) && approx(heading.y, 0.0, 1e-5) && approx(heading.z, 0.0, 1e-5));
+        assert!(glm::distance(&facing(&heading), &glm::vec2(1.0, 0.0)) < 1e-3);
+        assert!(approx(heading.pitch, 0.0, 1e-5) && approx(heading.roll, 0.0, 1e-5));
+
+        //Open paths stop at their ends
+        let end = path.heading_at(30.0, 10.0);
+        assert!(approx(end.x, 20.0, 1e-3) && glm::distance(&facing(&end), &glm::vec2(1.0, 0.0)) < 1e-3);
+        assert!(approx(path.heading_at(-5.0, 10.0).x, 0.0, 1e-3));
+    }
+
+    #[test]
+    fn climbing_path_headings() {
+        let path = FlightPath::new(vec![glm::vec3(0.0, 0.0, 0.0), glm::vec3(0.0, 10.0, -10.0)], SplineKind::CatmullRom, false);
+        let heading = path.heading_at(path.length() / 2.0, 4.0);
+        assert!(approx(heading.y, 5.0, 0.05) && approx(heading.z, -5.0, 0.05));
+        assert!(glm::distance(&facing(&heading), &glm::vec2(0.0, -1.0)) < 1e-3);
+        assert!(approx(heading.pitch, PI / 4.0, 1e-3));
+    }
+
+    #[test]
+    fn circle_headings() {
+        //Counterclockwise seen from above, so turning left
+        let radius = 20.0;
+        let points = (0..16).map(|i| {
+            let angle = i as f32 / 16.0 * 2.0 * PI;
+            glm::vec3(radius * angle.cos(), 5.0, -radius * angle.sin())
+        }).collect();
+        let path = FlightPath::new(points, SplineKind::CatmullRom, true);
+        assert!(approx(path.length(), 2.0 * PI * radius, 0.5));
+
+        let speed = 15.0;
+        let banked = (speed * speed / (GRAVITY * radius)).atan();
+        for &distance in &[0.0, 10.0, 47.0] {
+            let heading = path.heading_at(distance, speed);
+            let position = glm::vec3(heading.x, heading.y, heading.z);
+            assert!(approx(glm::length(&glm::vec2(position.x, position.z)), radius, 0.1), "{}", distance);
+            assert!(approx(heading.roll, banked, 0.05), "{} {}", heading.roll, banked);
+            assert!(approx(heading.pitch, 0.0, 1e-3));
+        }
+
+        //Closed paths loop
+        let (a, b) = (path.position_at(3.0), path.position_at(path.length() + 3.0));
+        assert!(glm::distance(&a, &b) < 1e-3);
+    }
+
+    #[test]
+    fn flight_sequences_need_positive_speeds() {
+        let sequence = |cruise_speed: f32, vertical_speed: f32| ron::de::from_str::<FlightSequence>(&format!(
+            "(from: (0.0, 0.0, 0.0), to: (30.0, 0.0, 40.0), cruise_altitude: 10.0, cruise_speed: {:?}, vertical_speed: {:?}, hover_time: 1.0)",
+            cruise_speed, vertical_speed));
+        assert!(sequence(0.0, 2.0).is_err());
+        assert!(sequence(5.0, -2.0).is_err());
+
+        //Up 10, across 50 and down 10, with top speeds 1.875 times the average
+        let sequence = sequence(5.0, 2.0).unwrap();
+        assert!(approx(sequence.duration(), 1.875 * (10.0 / 2.0 + 50.0 / 5.0 + 10.0 / 2.0) + 2.0, 1e-4));
+        let cruising = sequence.heading(1.875 * 5.0 + 1.0 + 1.875 * 5.0);
+        assert!(approx(cruising.x, 15.0, 1e-3) && approx(cruising.y, 10.0, 1e-4) && approx(cruising.z, 20.0, 1e-3));
+        let landed = sequence.heading(100.0);
+        assert!(approx(landed.x, 30.0, 1e-4) && approx(landed.y, 0.0, 1e-4) && approx(landed.pitch, 0.0, 1e-5));
+    }
+}
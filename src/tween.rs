@@ -0,0 +1,379 @@
+extern crate nalgebra_glm as glm;
+
+use serde::{Deserialize, Serialize};
+
+use crate::animation::Interpolate;
+use crate::behaviour::{Behaviour, BehaviourDescription, FrameContext};
+use crate::scene_file::Rotation;
+use crate::scene_graph::SceneNode;
+
+// The usual easing curves, see https://easings.net for what they look like. They all go from 0 at t = 0 to 1 at
+// t = 1, but Back and Elastic overshoot on the way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    Smooth, // Smoothstep, starts and stops gently
+    QuadIn, QuadOut, QuadInOut,
+    CubicIn, CubicOut, CubicInOut,
+    SineIn, SineOut, SineInOut,
+    ExpoIn, ExpoOut, ExpoInOut,
+    BackIn, BackOut, BackInOut,
+    ElasticIn, ElasticOut, ElasticInOut,
+    BounceIn, BounceOut, BounceInOut,
+}
+
+enum Mode {
+    In,
+    Out,
+    InOut,
+}
+
+fn quad(t: f32) -> f32 {
+    t * t
+}
+
+fn cubic(t: f32) -> f32 {
+    t * t * t
+}
+
+fn sine(t: f32) -> f32 {
+    1.0 - (t * std::f32::consts::FRAC_PI_2).cos()
+}
+
+fn expo(t: f32) -> f32 {
+    if t <= 0.0 { 0.0 } else { 2f32.powf(10.0 * t - 10.0) }
+}
+
+fn back(t: f32) -> f32 {
+    let c = 1.70158; //Gives a 10% overshoot
+    t * t * ((c + 1.0) * t - c)
+}
+
+fn elastic(t: f32) -> f32 {
+    if t <= 0.0 || t >= 1.0 {
+        return t.clamp(0.0, 1.0);
+    }
+    -(2f32.powf(10.0 * t - 10.0)) * ((10.0 * t - 10.75) * 2.0 * std::f32::consts::PI / 3.0).sin()
+}
+
+fn bounce(t: f32) -> f32 {
+    //Written as bouncing out, with four bounces, and flipped to bounce in
+    let t = 1.0 - t;
+    let (n, d) = (7.5625, 2.75);
+    let out = if t < 1.0 / d {
+        n * t * t
+    } else if t < 2.0 / d {
+        let t = t - 1.5 / d;
+        n * t * t + 0.75
+    } else if t < 2.5 / d {
+        let t = t - 2.25 / d;
+        n * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / d;
+        n * t * t + 0.984375
+    };
+    1.0 - out
+}
+
+impl Easing {
+    // Where on the way from 0 to 1 we are, a fraction t of the time into it. t is clamped to [0, 1]
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        let (curve, mode): (fn(f32) -> f32, Mode) = match self {
+            Easing::Linear => return t,
+            Easing::Smooth => return t * t * (3.0 - 2.0 * t),
+            Easing::QuadIn => (quad, Mode::In),
+            Easing::QuadOut => (quad, Mode::Out),
+            Easing::QuadInOut => (quad, Mode::InOut),
+            Easing::CubicIn => (cubic, Mode::In),
+            Easing::CubicOut => (cubic, Mode::Out),
+            Easing::CubicInOut => (cubic, Mode::InOut),
+            Easing::SineIn => (sine, Mode::In),
+            Easing::SineOut => (sine, Mode::Out),
+            Easing::SineInOut => (sine, Mode::InOut),
+            Easing::ExpoIn => (expo, Mode::In),
+            Easing::ExpoOut => (expo, Mode::Out),
+            Easing::ExpoInOut => (expo, Mode::InOut),
+            Easing::BackIn => (back, Mode::In),
+            Easing::BackOut => (back, Mode::Out),
+            Easing::BackInOut => (back, Mode::InOut),
+            Easing::ElasticIn => (elastic, Mode::In),
+            Easing::ElasticOut => (elastic, Mode::Out),
+            Easing::ElasticInOut => (elastic, Mode::InOut),
+            Easing::BounceIn => (bounce, Mode::In),
+            Easing::BounceOut => (bounce, Mode::Out),
+            Easing::BounceInOut => (bounce, Mode::InOut),
+        };
+        // Every curve is written as easing in, the others are made by flipping it
+        match mode {
+            Mode::In => curve(t),
+            Mode::Out => 1.0 - curve(1.0 - t),
+            Mode::InOut => if t < 0.5 { curve(2.0 * t) / 2.0 } else { 1.0 - curve(2.0 - 2.0 * t) / 2.0 },
+        }
+    }
+}
+
+// Goes from one value to another over some time, following an easing curve
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tween<T> {
+    pub from: T,
+    pub to: T,
+    pub duration: f32, // Seconds
+    pub easing: Easing,
+    time: f32,
+}
+
+impl<T: Interpolate> Tween<T> {
+    pub fn new(from: T, to: T, duration: f32, easing: Easing) -> Tween<T> {
+        Tween { from, to, duration, easing, time: 0.0 }
+    }
+
+    pub fn value(&self) -> T {
+        let t = if self.duration > 0.0 { self.time / self.duration } else { 1.0 };
+        T::lerp(&self.from, &self.to, self.easing.apply(t))
+    }
+
+    // Move to time seconds into the tween and return the value there
+    pub fn seek(&mut self, time: f32) -> T {
+        self.time = time.max(0.0).min(self.duration.max(0.0));
+        self.value()
+    }
+
+    pub fn finished(&self) -> bool {
+        self.time >= self.duration
+    }
+}
+
+// The node properties that can be tweened in scene files, and the values they go between
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TweenProperty {
+    Position { from: [f32; 3], to: [f32; 3] },
+    Scale { from: [f32; 3], to: [f32; 3] },
+    UniformScale { from: f32, to: f32 }, // The same scale along every axis
+    Orientation { from: Rotation, to: Rotation },
+}
+
+// Tweens a property of a node, found by its path relative to the node this is attached to. The tween starts at the
+// time of its first update and follows the simulation time, so seeking back to before it finished plays it again.
+// Once the tween is finished it leaves the property alone, so other behaviours can take over.
+pub struct NodeTween<T> {
+    pub node: String,
+    pub tween: Tween<T>,
+    set: fn(&mut SceneNode, T),
+    property: Option<fn(&Tween<T>) -> TweenProperty>, // Which TweenProperty this is, if it is one of them
+    start_time: Option<f32>,
+}
+
+impl<T: Interpolate> NodeTween<T> {
+    // Tween whatever set sets. Tweens made like this can't be saved in scene files
+    pub fn new(node: &str, tween: Tween<T>, set: fn(&mut SceneNode, T)) -> NodeTween<T> {
+        NodeTween { node: node.to_string(), tween, set, property: None, start_time: None }
+    }
+
+    fn of_property(node: &str, tween: Tween<T>, set: fn(&mut SceneNode, T), property: fn(&Tween<T>) -> TweenProperty) -> NodeTween<T> {
+        NodeTween { property: Some(property), ..NodeTween::new(node, tween, set) }
+    }
+}
+
+impl NodeTween<glm::Vec3> {
+    pub fn position(node: &str, tween: Tween<glm::Vec3>) -> NodeTween<glm::Vec3> {
+        NodeTween::of_property(node, tween, |node, position| node.position = position,
+            |tween| TweenProperty::Position { from: tween.from.into(), to: tween.to.into() })
+    }
+
+    pub fn scale(node: &str, tween: Tween<glm::Vec3>) -> NodeTween<glm::Vec3> {
+        NodeTween::of_property(node, tween, |node, scale| node.scale = scale,
+            |tween| TweenProperty::Scale { from: tween.from.into(), to: tween.to.into() })
+    }
+}
+
+impl NodeTween<f32> {
+    // Scale the node by the same amount along every axis
+    pub fn uniform_scale(node: &str, tween: Tween<f32>) -> NodeTween<f32> {
+        NodeTween::of_property(node, tween, |node, scale| node.scale = glm::vec3(scale, scale, scale),
+            |tween| TweenProperty::UniformScale { from: tween.from, to: tween.to })
+    }
+}
+
+impl NodeTween<glm::Quat> {
+    pub fn orientation(node: &str, tween: Tween<glm::Quat>) -> NodeTween<glm::Quat> {
+        NodeTween::of_property(node, tween, |node, orientation| node.orientation = orientation,
+            |tween| TweenProperty::Orientation { from: Rotation::from_quat(&tween.from), to: Rotation::from_quat(&tween.to) })
+    }
+}
+
+impl TweenProperty {
+    // The behaviour tweening this property of the node at the path node
+    pub fn create(&self, node: &str, duration: f32, easing: Easing) -> Box<dyn Behaviour> {
+        match *self {
+            TweenProperty::Position { from, to } => Box::new(NodeTween::position(node, Tween::new(glm::make_vec3(&from), glm::make_vec3(&to), duration, easing))),
+            TweenProperty::Scale { from, to } => Box::new(NodeTween::scale(node, Tween::new(glm::make_vec3(&from), glm::make_vec3(&to), duration, easing))),
+            TweenProperty::UniformScale { from, to } => Box::new(NodeTween::uniform_scale(node, Tween::new(from, to, duration, easing))),
+            TweenProperty::Orientation { from, to } => Box::new(NodeTween::orientation(node, Tween::new(from.to_quat(), to.to_quat(), duration, easing))),
+        }
+    }
+}
+
+impl<T: Interpolate> Behaviour for NodeTween<T> {
+    fn update(&mut self, node: &mut SceneNode, ctx: &FrameContext) {
+        let was_finished = self.start_time.is_some() && self.tween.finished();
+        let start_time = *self.start_time.get_or_insert(ctx.time);
+        let value = self.tween.seek(ctx.time - start_time);
+        if was_finished && self.tween.finished() {
+            return;
+        }
+        if let Some(target) = node.find(&self.node) {
+            (self.set)(target, value);
+        }
+    }
+
+    fn describe(&self) -> Option<BehaviourDescription> {
+        self.property.map(|property| BehaviourDescription::Tween {
+            node: self.node.clone(),
+            property: property(&self.tween),
+            duration: self.tween.duration,
+            easing: self.tween.easing,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASINGS: [Easing; 23] = [
+        Easing::Linear, Easing::Smooth,
+        Easing::QuadIn, Easing::QuadOut, Easing::QuadInOut,
+        Easing::CubicIn, Easing::CubicOut, Easing::CubicInOut,
+        Easing::SineIn, Easing::SineOut, Easing::SineInOut,
+        Easing::ExpoIn, Easing::ExpoOut, Easing::ExpoInOut,
+        Easing::BackIn, Easing::BackOut, Easing::BackInOut,
+        Easing::ElasticIn, Easing::ElasticOut, Easing::ElasticInOut,
+        Easing::BounceIn, Easing::BounceOut, Easing::BounceInOut,
+    ];
+
+    // The ones that overshoot or bounce back on the way
+    fn is_monotonic(easing: Easing) -> bool {
+        !matches!(easing,
+            Easing::BackIn | Easing::BackOut | Easing::BackInOut |
+            Easing::ElasticIn | Easing::ElasticOut | Easing::ElasticInOut |
+            Easing::BounceIn | Easing::BounceOut | Easing::BounceInOut)
+    }
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn easings_start_at_0_and_end_at_1() {
+        for &easing in &EASINGS {
+            assert!(approx(easing.apply(0.0), 0.0), "{:?} starts at {}", easing, easing.apply(0.0));
+            assert!(approx(easing.apply(1.0), 1.0), "{:?} ends at {}", easing, easing.apply(1.0));
+            //t is clamped
+            assert_eq!(easing.apply(-1.0), easing.apply(0.0));
+            assert_eq!(easing.apply(2.0), easing.apply(1.0));
+        }
+    }
+
+    #[test]
+    fn easings_are_monotonic_unless_they_overshoot() {
+        for &easing in EASINGS.iter().filter(|&&easing| is_monotonic(easing)) {
+            let values: Vec<f32> = (0..=200).map(|i| easing.apply(i as f32 / 200.0)).collect();
+            assert!(values.windows(2).all(|pair| pair[1] >= pair[0]), "{:?}", easing);
+        }
+        let overshoots = |easing: Easing| (0..=200).map(|i| easing.apply(i as f32 / 200.0)).any(|value| !(0.0..=1.0).contains(&value));
+        for &easing in &[Easing::BackIn, Easing::BackOut, Easing::ElasticOut] {
+            assert!(overshoots(easing), "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn in_out_easings_are_half_way_at_the_middle() {
+        for &easing in &[Easing::Smooth, Easing::QuadInOut, Easing::CubicInOut, Easing::SineInOut, Easing::ExpoInOut, Easing::BackInOut, Easing::ElasticInOut, Easing::BounceInOut] {
+            assert!(approx(easing.apply(0.5), 0.5), "{:?}", easing);
+        }
+    }
+
+    #[test]
+    fn tweens_stop_at_the_ends() {
+        let mut tween = Tween::new(0.0, 10.0, 2.0, Easing::Linear);
+        assert_eq!(tween.value(), 0.0);
+        assert_eq!(tween.seek(0.5), 2.5);
+        assert!(!tween.finished());
+        assert_eq!(tween.seek(10.0), 10.0);
+        assert!(tween.finished());
+        assert_eq!(tween.seek(-1.0), 0.0);
+        assert_eq!(Tween::new(1.0, 3.0, 0.0, Easing::QuadIn).value(), 3.0);
+    }
+
+    // Updates a behaviour on a parent node whose only child is called "part", at the given simulation times
+    fn step(behaviour: &mut dyn Behaviour, parent: &mut SceneNode, times: &[f32]) {
+        for &time in times {
+            behaviour.update(parent, &FrameContext { time, ..Default::default() });
+        }
+    }
+
+    fn parent_with_part() -> (crate::scene_graph::Node, crate::scene_graph::Node) {
+        let (mut parent, mut part) = (SceneNode::new(), SceneNode::new());
+        part.name = "part".to_string();
+        parent.add_child(&part);
+        (parent, part)
+    }
+
+    #[test]
+    fn node_tweens_set_their_property() {
+        let (mut parent, part) = parent_with_part();
+        let mut position = NodeTween::position("part", Tween::new(glm::zero(), glm::vec3(2.0, 4.0, 0.0), 1.0, Easing::Linear));
+        step(&mut position, &mut parent, &[2.0, 2.5]); //Starting at the first update
+        assert_eq!(part.position, glm::vec3(1.0, 2.0, 0.0));
+
+        let mut scale = NodeTween::scale("part", Tween::new(glm::vec3(1.0, 1.0, 1.0), glm::vec3(3.0, 1.0, 1.0), 1.0, Easing::Linear));
+        step(&mut scale, &mut parent, &[0.0, 0.5]);
+        assert_eq!(part.scale, glm::vec3(2.0, 1.0, 1.0));
+
+        let mut uniform_scale = NodeTween::uniform_scale("part", Tween::new(0.0, 1.0, 1.0, Easing::Linear));
+        step(&mut uniform_scale, &mut parent, &[0.0, 0.25]);
+        assert_eq!(part.scale, glm::vec3(0.25, 0.25, 0.25));
+
+        let quarter_turn = glm::quat_angle_axis(std::f32::consts::FRAC_PI_2, &glm::vec3(0.0, 1.0, 0.0));
+        let mut orientation = NodeTween::orientation("part", Tween::new(glm::quat_identity(), quarter_turn, 1.0, Easing::Linear));
+        step(&mut orientation, &mut parent, &[0.0, 0.5]);
+        let eighth_turn = glm::quat_angle_axis(std::f32::consts::FRAC_PI_4, &glm::vec3(0.0, 1.0, 0.0));
+        assert!(approx(glm::quat_dot(&part.orientation, &eighth_turn).abs(), 1.0));
+    }
+
+    #[test]
+    fn finished_node_tweens_leave_the_property_alone() {
+        let (mut parent, mut part) = parent_with_part();
+        let mut tween = NodeTween::position("part", Tween::new(glm::zero(), glm::vec3(1.0, 0.0, 0.0), 1.0, Easing::Smooth));
+        step(&mut tween, &mut parent, &[0.0, 2.0]);
+        assert_eq!(part.position, glm::vec3(1.0, 0.0, 0.0));
+        part.position = glm::vec3(5.0, 0.0, 0.0);
+        step(&mut tween, &mut parent, &[2.1, 3.0]);
+        assert_eq!(part.position, glm::vec3(5.0, 0.0, 0.0));
+
+        //Until the time goes back to before it was done
+        step(&mut tween, &mut parent, &[0.5]);
+        assert_eq!(part.position, glm::vec3(0.5, 0.0, 0.0));
+
+        //Tweens that take no time still set the property once
+        let mut instant = NodeTween::position("part", Tween::new(glm::zero(), glm::vec3(0.0, 7.0, 0.0), 0.0, Easing::Linear));
+        step(&mut instant, &mut parent, &[4.0]);
+        assert_eq!(part.position, glm::vec3(0.0, 7.0, 0.0));
+    }
+
+    #[test]
+    fn node_tweens_are_described_the_way_they_were_made() {
+        let descriptions = [
+            TweenProperty::Position { from: [0.0, 1.0, 2.0], to: [3.0, 4.0, 5.0] },
+            TweenProperty::Scale { from: [1.0, 1.0, 1.0], to: [2.0, 1.0, 2.0] },
+            TweenProperty::UniformScale { from: 0.0, to: 1.0 },
+            TweenProperty::Orientation { from: Rotation::Quaternion([0.0, 0.0, 0.0, 1.0]), to: Rotation::Quaternion([0.0, 0.6, 0.0, 0.8]) },
+        ].iter().map(|&property| BehaviourDescription::Tween { node: "part".to_string(), property, duration: 0.6, easing: Easing::BackOut });
+        for description in descriptions {
+            assert_eq!(description.create().describe(), Some(description));
+        }
+        assert_eq!(NodeTween::new("", Tween::new(0.0, 1.0, 1.0, Easing::Linear), |node, x| node.position.x = x).describe(), None);
+    }
+}
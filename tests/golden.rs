@@ -0,0 +1,17 @@
+// Renders the golden image cases and compares them with the reference images, see src/golden.rs. It needs a GPU
+// or a software OpenGL driver, so it only runs when asked for, with
+//
+//     cargo test --test golden -- --ignored
+
+use std::process::Command;
+
+#[test]
+#[ignore]
+fn golden_images_match() {
+    let status = Command::new(env!("CARGO_BIN_EXE_gloom-rs"))
+        .args(["--golden", "golden/cases.ron"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("failed to start gloom-rs");
+    assert!(status.success(), "golden image tests failed, the images that differ are in golden_output");
+}